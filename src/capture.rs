@@ -0,0 +1,64 @@
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+#[cfg(feature = "sdl")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use palette::Palette;
+use png;
+
+// Render a screen buffer of width x height pixels into RGB bytes, each
+// pixel scaled up to a scale x scale square in the palette's colors.
+pub fn screen_rgb(screen: &[bool], width: usize, height: usize,
+                  scale: usize, palette: &Palette) -> Vec<u8> {
+    let mut rgb = Vec::with_capacity(width * height * scale * scale * 3);
+
+    for y in 0..(height * scale) {
+        for x in 0..(width * scale) {
+            let color = if screen[(y / scale) * width + x / scale] {
+                palette.foreground()
+            } else {
+                palette.background()
+            };
+            rgb.extend_from_slice(&color);
+        }
+    }
+
+    rgb
+}
+
+// The current UTC time as YYYYMMDD-HHMMSS.
+#[cfg(feature = "sdl")]
+pub fn timestamp() -> String {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+
+    // Civil date from days since the epoch (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}{:02}{:02}-{:02}{:02}{:02}",
+            year, month, day, rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
+// A file name for a capture, e.g. pong-20261018-153012.png.
+#[cfg(feature = "sdl")]
+pub fn capture_name(prefix: &str, ext: &str) -> String {
+    format!("{}-{}.{}", prefix, timestamp(), ext)
+}
+
+// Save a screen buffer as a PNG scaled by scale.
+pub fn save_screenshot(fname: &str, screen: &[bool], width: usize, height: usize,
+                       scale: usize, palette: &Palette) -> io::Result<()> {
+    let rgb = screen_rgb(screen, width, height, scale, palette);
+    let mut out = BufWriter::new(File::create(fname)?);
+
+    png::write_png(&mut out, (width * scale) as u32, (height * scale) as u32, &rgb)
+}
@@ -0,0 +1,437 @@
+use chip8::frontend::ScaleMode;
+use chip8::palette::Palette;
+use chip8::filter::DisplayFilter;
+use chip8::keymap::KeyMap;
+use chip8::random::RandomKind;
+use chip8::quirks::Quirks;
+use chip8::config::Settings;
+
+// Command line options. Each has a long form, and the ones used most also
+// a short one; values follow as the next argument or after an =, as in
+// --scale 8 or --scale=8. Settings that a config file can also give are
+// None unless given here, and then take precedence.
+
+pub const USAGE: &str = "\
+Usage: chip8 [options] <rom>
+
+Running:
+  -i, --ips <n>             Instructions per second (default 600)
+  -Q, --quirks <profile>    Interpreter quirks: classic (default), vip or schip
+      --platform <name>     The ROM's platform, for its quirks: chip8 or schip
+  -S, --seed <n>            Seed for random numbers, in decimal or 0x hex
+  -V, --vip-random          Random numbers like the COSMAC VIP's interpreter
+      --load-address <addr> Where the ROM is loaded and run from (default 0x200)
+  -u, --unthrottled         Run as fast as possible
+      --frames <n>          Stop after n frames, at 60 per emulated second
+      --headless            Run without display or input; implies --unthrottled
+
+Display:
+  -t, --terminal            Draw in the terminal instead of a window
+  -s, --scale <n>           Window size as a multiple of 64x32 (default 4)
+  -m, --scale-mode <mode>   integer (default), aspect or stretch
+  -f, --fullscreen          Start fullscreen
+  -v, --vsync               Present in step with the display's refresh
+  -P, --palette <palette>   A theme (classic, amber, green, lcd) or palette file
+  -F, --filter <filters>    Comma separated: none, or, phosphor, scanlines
+  -K, --keymap <keymap>     A layout (qwerty, azerty, dvorak) or key map file
+
+Recording:
+  -R, --record <file>       Record the screen to a .gif, .png or .apng file
+  -W, --record-raw          Also save every frame and the sound
+  -M, --record-movie <file> Record the keys pressed, to play back later
+  -Y, --play-movie <file>   Play back a movie instead of reading the keys
+
+Settings:
+      --config <file>       Read settings from file instead of the default,
+                            ~/.config/chip8/config.toml
+      --no-config           Don't read a config file
+      --info                Print the ROM's SHA-1, what the ROM database and
+                            config say about it, and its settings, then exit
+
+Debugging:
+  -d, --debug               Start in the console debugger
+  -g, --gui-debug           Start in the graphical debugger
+      --trace <file>        Write every instruction executed to file
+  -p, --profile             Print a profile of where time went on exit
+  -c, --coverage            Write coverage reports next to the ROM on exit
+  -h, --help                Show this help
+";
+
+pub struct Options {
+    pub rom: String,
+
+    pub settings: Settings, // ips, quirks, platform, palette and keymap
+    pub config: Option<String>,
+    pub no_config: bool,
+    pub info: bool,
+
+    pub seed: Option<u64>,
+    pub random: RandomKind,
+    pub load_address: u16,
+    pub unthrottled: bool,
+    pub frames: Option<u64>,
+    pub headless: bool,
+
+    pub terminal: bool,
+    pub scale: u32,
+    pub scale_mode: ScaleMode,
+    pub fullscreen: bool,
+    pub vsync: bool,
+    pub filter: DisplayFilter,
+
+    pub record: Option<String>,
+    pub record_raw: bool,
+    pub record_movie: Option<String>,
+    pub play_movie: Option<String>,
+
+    pub debug: bool,
+    pub gui_debug: bool,
+    pub trace: Option<String>,
+    pub profile: bool,
+    pub coverage: bool,
+}
+
+// A number in decimal, or in hex with 0x in front.
+fn parse_number(s: &str) -> Option<u64> {
+    if s.starts_with("0x") || s.starts_with("0X") {
+        u64::from_str_radix(&s[2..], 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+impl Options {
+    // Parse the arguments after the program name. Returns None if help was
+    // asked for, and a message saying what is wrong on errors.
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Option<Options>, String> {
+        let mut o = Options {
+            rom: String::new(),
+
+            settings: Settings::default(),
+            config: None,
+            no_config: false,
+            info: false,
+
+            seed: None,
+            random: RandomKind::Xorshift,
+            load_address: 0x200,
+            unthrottled: false,
+            frames: None,
+            headless: false,
+
+            terminal: false,
+            scale: 4,
+            scale_mode: ScaleMode::Integer,
+            fullscreen: false,
+            vsync: false,
+            filter: DisplayFilter::new(),
+
+            record: None,
+            record_raw: false,
+            record_movie: None,
+            play_movie: None,
+
+            debug: false,
+            gui_debug: false,
+            trace: None,
+            profile: false,
+            coverage: false,
+        };
+        let mut rom = None;
+
+        while let Some(arg) = args.next() {
+            // --name=value
+            let (name, mut inline) = match arg.find('=') {
+                Some(i) if arg.starts_with("--") => {
+                    (arg[..i].to_string(), Some(arg[(i + 1)..].to_string()))
+                },
+                _ => (arg.clone(), None),
+            };
+            let mut value = |what: &str| -> Result<String, String> {
+                match inline.take().or_else(|| args.next()) {
+                    Some(v) => Ok(v),
+                    None => Err(format!("{} takes {}", name, what)),
+                }
+            };
+
+            match name.as_str() {
+                "-h" | "--help" => return Ok(None),
+
+                "-i" | "--ips" => {
+                    let v = value("a number of instructions per second")?;
+                    o.settings.ips = match v.parse() {
+                        Ok(ips) if ips > 0 => Some(ips),
+                        _ => return Err(format!("{} takes a whole number above zero, not {:?}",
+                                                name, v)),
+                    };
+                },
+                "-Q" | "--quirks" => {
+                    let v = value("a quirk profile")?;
+                    o.settings.quirks = match Quirks::profile(&v) {
+                        Some(q) => Some(q),
+                        None => return Err(format!("Unknown quirk profile {:?} (profiles are {})",
+                                                   v, Quirks::profile_names().join(", "))),
+                    };
+                },
+                "--platform" => {
+                    let v = value("a platform")?;
+                    if Quirks::platform(&v).is_none() {
+                        return Err(format!("Unknown platform {:?} (platforms are {})",
+                                           v, Quirks::platform_names().join(", ")));
+                    }
+                    o.settings.platform = Some(v);
+                },
+                "-S" | "--seed" => {
+                    let v = value("a seed")?;
+                    o.seed = match parse_number(&v) {
+                        Some(seed) => Some(seed),
+                        None => return Err(format!("{} takes a number in decimal or 0x hex, \
+                                                    not {:?}", name, v)),
+                    };
+                },
+                "-V" | "--vip-random" => o.random = RandomKind::Vip,
+                "--load-address" => {
+                    let v = value("an address")?;
+                    // Below 0x50 is the font
+                    o.load_address = match parse_number(&v) {
+                        Some(addr) if (0x50..0x1000).contains(&addr) => addr as u16,
+                        _ => return Err(format!("{} takes an address from 0x050 to 0xFFF, not {:?}",
+                                                name, v)),
+                    };
+                },
+                "-u" | "--unthrottled" => o.unthrottled = true,
+                "--frames" => {
+                    let v = value("a number of frames")?;
+                    o.frames = match v.parse() {
+                        Ok(n) => Some(n),
+                        Err(_) => return Err(format!("{} takes a whole number, not {:?}", name, v)),
+                    };
+                },
+                "--headless" => o.headless = true,
+
+                "-t" | "--terminal" => o.terminal = true,
+                "-s" | "--scale" => {
+                    let v = value("a scale factor")?;
+                    o.scale = match v.parse() {
+                        Ok(scale) if scale > 0 => scale,
+                        _ => return Err(format!("{} takes a whole number above zero, not {:?}",
+                                                name, v)),
+                    };
+                },
+                "-m" | "--scale-mode" => {
+                    let v = value("a scale mode")?;
+                    o.scale_mode = match ScaleMode::parse(&v) {
+                        Some(mode) => mode,
+                        None => return Err(format!("{} takes one of integer, aspect or stretch, \
+                                                    not {:?}", name, v)),
+                    };
+                },
+                "-f" | "--fullscreen" => o.fullscreen = true,
+                "-v" | "--vsync" => o.vsync = true,
+                "-P" | "--palette" => {
+                    let v = value("a theme name or palette file")?;
+                    o.settings.palette = Some(Palette::from_arg(&v).map_err(|e| {
+                        format!("{} (themes are {})", e, Palette::theme_names().join(", "))
+                    })?);
+                },
+                "-F" | "--filter" => o.filter = DisplayFilter::parse(&value("a list of filters")?)?,
+                "-K" | "--keymap" => {
+                    let v = value("a key map preset or file")?;
+                    o.settings.keymap = Some(KeyMap::from_arg(&v).map_err(|e| {
+                        format!("{} (presets are {})", e, KeyMap::preset_names().join(", "))
+                    })?);
+                },
+
+                "--config" => o.config = Some(value("a config file")?),
+                "--no-config" => o.no_config = true,
+                "--info" => o.info = true,
+
+                "-R" | "--record" => o.record = Some(value("a .gif, .png or .apng file")?),
+                "-W" | "--record-raw" => o.record_raw = true,
+                "-M" | "--record-movie" => o.record_movie = Some(value("a movie file")?),
+                "-Y" | "--play-movie" => o.play_movie = Some(value("a movie file")?),
+
+                "-d" | "--debug" => o.debug = true,
+                "-g" | "--gui-debug" => o.gui_debug = true,
+                "--trace" => o.trace = Some(value("a file to write to")?),
+                "-p" | "--profile" => o.profile = true,
+                "-c" | "--coverage" => o.coverage = true,
+
+                _ if name.starts_with('-') && name.len() > 1 => {
+                    return Err(format!("Unknown option {}", name));
+                },
+                _ if rom.is_some() => {
+                    return Err(format!("Only one ROM can be run, but both {} and {} were given",
+                                       rom.unwrap(), name));
+                },
+                _ => rom = Some(name.clone()),
+            }
+
+            if inline.is_some() {
+                return Err(format!("{} doesn't take a value", name));
+            }
+        }
+
+        o.rom = match rom {
+            Some(rom) => rom,
+            None => return Err(String::from("No ROM given")),
+        };
+        o.settings.apply_platform();
+        o.check()?;
+
+        Ok(Some(o))
+    }
+
+    // Reject options that don't go together.
+    fn check(&self) -> Result<(), String> {
+        let sdl = !self.terminal && !self.headless;
+
+        if self.config.is_some() && self.no_config {
+            return Err(String::from("--config and --no-config can't both be given"));
+        }
+        if self.terminal && self.headless {
+            return Err(String::from("--terminal and --headless can't both be given"));
+        }
+        if self.terminal && !cfg!(unix) {
+            return Err(String::from("--terminal is only available on Unix"));
+        }
+        if sdl && !cfg!(feature = "sdl") {
+            return Err(String::from("Built without SDL, so there is no window; \
+                                     use --terminal or --headless"));
+        }
+        if self.gui_debug && !sdl {
+            return Err(String::from("--gui-debug needs the window, not --terminal or --headless"));
+        }
+        if self.debug && self.gui_debug {
+            return Err(String::from("--debug and --gui-debug can't both be given"));
+        }
+        // Movies go a frame at a time, so they only work when just running
+        if (self.record_movie.is_some() || self.play_movie.is_some()) &&
+            (self.debug || self.gui_debug) {
+            return Err(String::from("Movies can't be recorded or played back in the debugger"));
+        }
+        if self.record_movie.is_some() && self.play_movie.is_some() {
+            return Err(String::from("--record-movie and --play-movie can't both be given"));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Parse the given arguments, after --headless so that they don't need a
+    // window.
+    fn parse(args: &[&str]) -> Result<Option<Options>, String> {
+        let args = ["--headless"].iter().chain(args).map(|a| a.to_string());
+        Options::parse(args.collect::<Vec<_>>().into_iter())
+    }
+
+    fn options(args: &[&str]) -> Options {
+        parse(args).unwrap().unwrap()
+    }
+
+    fn error(args: &[&str]) -> String {
+        parse(args).err().unwrap()
+    }
+
+    #[test]
+    fn defaults() {
+        let o = options(&["game.ch8"]);
+        assert_eq!(o.rom, "game.ch8");
+        assert!(o.headless);
+        assert_eq!(o.settings.ips, None);
+        assert_eq!(o.settings.quirks, None);
+        assert_eq!(o.seed, None);
+        assert_eq!(o.random, RandomKind::Xorshift);
+        assert_eq!(o.load_address, 0x200);
+        assert_eq!(o.scale, 4);
+        assert_eq!(o.scale_mode, ScaleMode::Integer);
+        assert_eq!(o.frames, None);
+        assert!(!o.debug && !o.profile && !o.coverage);
+    }
+
+    #[test]
+    fn values() {
+        // Values can follow as the next argument or after an =
+        let o = options(&["-i", "1000", "--quirks=schip", "--seed", "0x5EED", "-V",
+                          "--load-address=0x600", "--frames", "120", "-s", "8",
+                          "--scale-mode", "stretch", "-P", "amber", "-F", "scanlines",
+                          "-K", "azerty", "-M", "run.movie", "-p", "game.ch8"]);
+        assert_eq!(o.settings.ips, Some(1000));
+        assert_eq!(o.settings.quirks, Quirks::profile("schip"));
+        assert_eq!(o.seed, Some(0x5EED));
+        assert_eq!(o.random, RandomKind::Vip);
+        assert_eq!(o.load_address, 0x600);
+        assert_eq!(o.frames, Some(120));
+        assert_eq!(o.scale, 8);
+        assert_eq!(o.scale_mode, ScaleMode::Stretch);
+        assert_eq!(o.settings.palette, Palette::theme("amber"));
+        assert!(o.filter.scanlines);
+        assert_eq!(o.settings.keymap, KeyMap::preset("azerty"));
+        assert_eq!(o.record_movie.as_deref(), Some("run.movie"));
+        assert!(o.profile);
+        assert_eq!(o.seed, options(&["--seed=24301", "game.ch8"]).seed);
+    }
+
+    #[test]
+    fn platform() {
+        let o = options(&["--platform", "schip", "game.ch8"]);
+        assert_eq!(o.settings.quirks, Quirks::profile("schip"));
+
+        // Quirks given alongside the platform win
+        let o = options(&["--platform", "schip", "-Q", "vip", "game.ch8"]);
+        assert_eq!(o.settings.quirks, Quirks::profile("vip"));
+    }
+
+    #[test]
+    fn help() {
+        assert!(parse(&["-h"]).unwrap().is_none());
+        assert!(parse(&["game.ch8", "--help"]).unwrap().is_none());
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error(&[]), "No ROM given");
+        assert_eq!(error(&["a.ch8", "b.ch8"]),
+                   "Only one ROM can be run, but both a.ch8 and b.ch8 were given");
+        assert_eq!(error(&["--bogus", "game.ch8"]), "Unknown option --bogus");
+        assert_eq!(error(&["game.ch8", "--ips"]), "--ips takes a number of instructions per second");
+        assert_eq!(error(&["-i", "0", "game.ch8"]),
+                   "-i takes a whole number above zero, not \"0\"");
+        assert_eq!(error(&["--seed", "0xZZ", "game.ch8"]),
+                   "--seed takes a number in decimal or 0x hex, not \"0xZZ\"");
+        assert_eq!(error(&["--load-address", "0x20", "game.ch8"]),
+                   "--load-address takes an address from 0x050 to 0xFFF, not \"0x20\"");
+        assert_eq!(error(&["--load-address", "0x1000", "game.ch8"]),
+                   "--load-address takes an address from 0x050 to 0xFFF, not \"0x1000\"");
+        assert_eq!(error(&["-m", "zoom", "game.ch8"]),
+                   "-m takes one of integer, aspect or stretch, not \"zoom\"");
+        assert_eq!(error(&["--profile=yes", "game.ch8"]), "--profile doesn't take a value");
+        assert!(error(&["-Q", "nonsense", "game.ch8"])
+                .starts_with("Unknown quirk profile \"nonsense\""));
+        assert!(error(&["--platform", "nonsense", "game.ch8"])
+                .starts_with("Unknown platform \"nonsense\""));
+    }
+
+    #[test]
+    fn conflicts() {
+        assert_eq!(error(&["--config", "c.toml", "--no-config", "game.ch8"]),
+                   "--config and --no-config can't both be given");
+        assert_eq!(error(&["-t", "game.ch8"]),
+                   "--terminal and --headless can't both be given");
+        assert_eq!(error(&["-g", "game.ch8"]),
+                   "--gui-debug needs the window, not --terminal or --headless");
+        assert_eq!(error(&["-d", "-Y", "run.movie", "game.ch8"]),
+                   "Movies can't be recorded or played back in the debugger");
+        assert_eq!(error(&["-M", "a.movie", "-Y", "b.movie", "game.ch8"]),
+                   "--record-movie and --play-movie can't both be given");
+
+        // Recording the screen works with every frontend
+        let o = options(&["-R", "out.gif", "-W", "game.ch8"]);
+        assert_eq!(o.record.as_deref(), Some("out.gif"));
+        assert!(o.record_raw);
+    }
+}
@@ -0,0 +1,488 @@
+//! The config file and the bundled ROM database.
+
+use std::env;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use palette::Palette;
+use keymap::KeyMap;
+use quirks::Quirks;
+use sha1;
+
+// The config file holds settings for every ROM, and for particular ROMs in
+// sections named by the SHA-1 of the ROM file (as printed by sha1sum). It
+// is written in a subset of TOML: key = value lines and [rom.<sha1>]
+// section headers, with comments from # to the end of the line. Strings
+// are in double quotes, with the escapes \", \\, \n, \t, \r, \uXXXX and
+// \UXXXXXXXX, or in single quotes, taken as they are. Numbers are plain
+// decimal. E.g.
+//
+//     ips = 700  # Faster than the default
+//     palette = 'C:\chip8\amber.pal'
+//
+//     [rom.0123456789abcdef0123456789abcdef01234567]
+//     title = "Some Game"
+//     platform = "schip"
+//     keymap = "some-game.keys"
+//
+// The keys are ips, quirks, platform (which picks the quirks, unless they
+// are given too), palette and keymap. ROM sections can also describe the
+// ROM with title, author, year and keys (hints on the controls). Key map
+// and palette files are found relative to the config file.
+//
+// The bundled ROM database is in the same format, with a section for each
+// known ROM.
+
+static DATABASE: &str = include_str!("romdb.toml");
+
+/// Settings from one part of the config, or from the command line. Each is
+/// None when not given.
+#[derive(Clone, Default)]
+pub struct Settings {
+    /// Instructions per second
+    pub ips: Option<u32>,
+    /// The quirks to run with
+    pub quirks: Option<Quirks>,
+    /// The platform the ROM was written for, e.g. "schip"
+    pub platform: Option<String>,
+    /// Display colors
+    pub palette: Option<Palette>,
+    /// Key bindings
+    pub keymap: Option<KeyMap>,
+}
+
+impl Settings {
+    /// A platform stands for its quirks, unless they're given as well.
+    pub fn apply_platform(&mut self) {
+        if self.quirks.is_none() {
+            self.quirks = self.platform.as_ref().and_then(|p| Quirks::platform(p));
+        }
+    }
+
+    /// Take the settings other gives in place of ours.
+    pub fn merge(&mut self, other: &Settings) {
+        if other.ips.is_some() {
+            self.ips = other.ips;
+        }
+        if other.quirks.is_some() {
+            self.quirks = other.quirks;
+        }
+        if other.platform.is_some() {
+            self.platform = other.platform.clone();
+        }
+        if other.palette.is_some() {
+            self.palette = other.palette;
+        }
+        if other.keymap.is_some() {
+            self.keymap = other.keymap.clone();
+        }
+    }
+}
+
+/// What a ROM section says about the ROM itself.
+#[derive(Clone, Debug, Default)]
+pub struct RomInfo {
+    /// Name of the game or program
+    pub title: Option<String>,
+    /// Who wrote it
+    pub author: Option<String>,
+    /// Year it was released
+    pub year: Option<u32>,
+    /// How to play, e.g. which keys do what
+    pub keys: Option<String>,
+}
+
+impl RomInfo {
+    /// Take the descriptions other gives in place of ours.
+    pub fn merge(&mut self, other: &RomInfo) {
+        if other.title.is_some() {
+            self.title = other.title.clone();
+        }
+        if other.author.is_some() {
+            self.author = other.author.clone();
+        }
+        if other.year.is_some() {
+            self.year = other.year;
+        }
+        if other.keys.is_some() {
+            self.keys = other.keys.clone();
+        }
+    }
+
+    /// One line, e.g. "Some Game by Someone (1990)".
+    pub fn describe(&self) -> String {
+        let mut s = self.title.clone().unwrap_or_else(|| String::from("Untitled"));
+        if let Some(ref author) = self.author {
+            s.push_str(&format!(" by {}", author));
+        }
+        if let Some(year) = self.year {
+            s.push_str(&format!(" ({})", year));
+        }
+        s
+    }
+}
+
+/// Settings read from the config file and the ROM database.
+#[derive(Default)]
+pub struct Config {
+    global: Settings,
+    roms: Vec<(String, Settings, RomInfo)>, // (SHA-1 in hex, settings, info)
+}
+
+/// What the config and the ROM database know a ROM by: the SHA-1 of its
+/// contents in hex, as sha1sum prints it.
+pub fn rom_hash(rom: &[u8]) -> String {
+    sha1::hex(rom)
+}
+
+// A string value: in single quotes, taken as it is, or in double quotes,
+// with backslash escapes.
+fn parse_string(val: &str) -> Option<String> {
+    if val.len() >= 2 && val.starts_with('\'') && val.ends_with('\'') {
+        let s = &val[1..(val.len() - 1)];
+        return if s.contains('\'') { None } else { Some(s.to_string()) };
+    }
+    if val.len() < 2 || !val.starts_with('"') || !val.ends_with('"') {
+        return None;
+    }
+
+    let mut s = String::new();
+    let mut chars = val[1..(val.len() - 1)].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return None,
+            '\\' => s.push(match chars.next()? {
+                '"' => '"',
+                '\\' => '\\',
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                c @ ('u' | 'U') => {
+                    let len = if c == 'u' { 4 } else { 8 };
+                    let hex: String = chars.by_ref().take(len).collect();
+                    if hex.len() != len {
+                        return None;
+                    }
+                    char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?
+                },
+                _ => return None,
+            }),
+            c => s.push(c),
+        }
+    }
+
+    Some(s)
+}
+
+// The line up to a comment, if it has one. A # in a string doesn't start
+// a comment.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => (),
+            None if c == '#' => return &line[..i],
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None => (),
+        }
+    }
+
+    line
+}
+
+// A file named in the config, relative to the config file's directory.
+fn config_file(dir: &Path, name: &str) -> String {
+    let path = dir.join(name);
+    if Path::new(name).is_relative() && path.exists() {
+        path.to_string_lossy().into_owned()
+    } else {
+        name.to_string()
+    }
+}
+
+impl Config {
+    /// Where the config file is looked for when none is given:
+    /// $XDG_CONFIG_HOME/chip8/config.toml, by default under ~/.config.
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(dir.join("chip8").join("config.toml"))
+    }
+
+    /// Read a config file.
+    pub fn load(fname: &str) -> Result<Config, String> {
+        let f = match File::open(fname) {
+            Ok(f) => f,
+            Err(_) => return Err(format!("Could not open config {}", fname)),
+        };
+        let mut text = String::new();
+        if BufReader::new(f).read_to_string(&mut text).is_err() {
+            return Err(format!("Could not read config {}", fname));
+        }
+
+        let dir = Path::new(fname).parent().unwrap_or_else(|| Path::new("."));
+        Config::parse(&text, fname, dir)
+    }
+
+    /// The bundled ROM database.
+    pub fn database() -> Config {
+        Config::parse(DATABASE, "romdb.toml", Path::new("")).unwrap()
+    }
+
+    /// Parse a config from text. fname is the name for errors, and dir
+    /// where the files it names are.
+    pub fn parse(text: &str, fname: &str, dir: &Path) -> Result<Config, String> {
+        let mut config = Config::default();
+        let mut section: Option<String> = None;
+        let mut settings = Settings::default();
+        let mut info = RomInfo::default();
+
+        for (n, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |what: String| format!("{} line {}: {}", fname, n + 1, what);
+
+            if line.starts_with('[') {
+                let name = match line.strip_prefix("[rom.").and_then(|l| l.strip_suffix(']')) {
+                    Some(hash) if hash.len() == 40 && hash.chars().all(|c| c.is_ascii_hexdigit()) => {
+                        hash.to_ascii_lowercase()
+                    },
+                    _ => return Err(error(format!("Expected [rom.<sha1>], found {}", line))),
+                };
+                config.finish_section(section.take(), settings, info);
+                section = Some(name);
+                settings = Settings::default();
+                info = RomInfo::default();
+                continue;
+            }
+
+            let (key, val) = match line.find('=') {
+                Some(i) => (line[..i].trim(), line[(i + 1)..].trim()),
+                None => return Err(error(format!("Expected key = value, found {}", line))),
+            };
+            let string = || parse_string(val)
+                .ok_or_else(|| error(format!("{} takes a string in quotes", key)));
+
+            match key {
+                "ips" => settings.ips = match val.parse() {
+                    Ok(ips) if ips > 0 => Some(ips),
+                    _ => return Err(error(String::from("ips takes a whole number above zero"))),
+                },
+                "quirks" => {
+                    let name = string()?;
+                    settings.quirks = match Quirks::profile(&name) {
+                        Some(q) => Some(q),
+                        None => return Err(error(format!(
+                            "Unknown quirk profile {:?} (profiles are {})",
+                            name, Quirks::profile_names().join(", ")))),
+                    };
+                },
+                "platform" => {
+                    let name = string()?;
+                    if Quirks::platform(&name).is_none() {
+                        return Err(error(format!("Unknown platform {:?} (platforms are {})",
+                                                 name, Quirks::platform_names().join(", "))));
+                    }
+                    settings.platform = Some(name);
+                },
+                "palette" => {
+                    let name = config_file(dir, &string()?);
+                    settings.palette = Some(Palette::from_arg(&name).map_err(&error)?);
+                },
+                "keymap" => {
+                    let name = config_file(dir, &string()?);
+                    settings.keymap = Some(KeyMap::from_arg(&name).map_err(&error)?);
+                },
+                "title" => info.title = Some(string()?),
+                "author" => info.author = Some(string()?),
+                "year" => info.year = match val.parse() {
+                    Ok(year) => Some(year),
+                    Err(_) => return Err(error(String::from("year takes a whole number"))),
+                },
+                "keys" => info.keys = Some(string()?),
+                _ => return Err(error(format!("Unknown setting {:?}", key))),
+            }
+        }
+        config.finish_section(section, settings, info);
+
+        Ok(config)
+    }
+
+    fn finish_section(&mut self, section: Option<String>, mut settings: Settings,
+                      info: RomInfo) {
+        settings.apply_platform();
+
+        match section {
+            Some(hash) => self.roms.push((hash, settings, info)),
+            None => self.global = settings,
+        }
+    }
+
+    /// The settings for every ROM.
+    pub fn global(&self) -> &Settings {
+        &self.global
+    }
+
+    /// The settings and description in the section for the ROM with the
+    /// given SHA-1, if there is one.
+    pub fn rom(&self, hash: &str) -> Option<(&Settings, &RomInfo)> {
+        self.roms.iter().rev()
+            .find(|r| r.0 == hash)
+            .map(|r| (&r.1, &r.2))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    fn parse(text: &str) -> Result<Config, String> {
+        Config::parse(text, "test.toml", Path::new(""))
+    }
+
+    #[test]
+    fn global_and_rom_sections() {
+        let config = parse("# Settings for everything\n\
+                            ips = 700\n\
+                            palette = \"amber\"\n\
+                            \n\
+                            [rom.0123456789ABCDEF0123456789ABCDEF01234567]\n\
+                            title = \"Some Game\"\n\
+                            author = \"Someone\"\n\
+                            year = 1991\n\
+                            keys = \"5 fires\"\n\
+                            quirks = \"schip\"\n").unwrap();
+
+        assert_eq!(config.global().ips, Some(700));
+        assert_eq!(config.global().palette, Palette::theme("amber"));
+        assert_eq!(config.global().quirks, None);
+
+        // Section names are matched in lower case
+        let (settings, info) = config.rom(HASH).unwrap();
+        assert_eq!(settings.ips, None);
+        assert_eq!(settings.quirks, Quirks::profile("schip"));
+        assert_eq!(info.describe(), "Some Game by Someone (1991)");
+        assert_eq!(info.keys.as_deref(), Some("5 fires"));
+
+        assert!(config.rom("89abcdef0123456789abcdef0123456789abcdef").is_none());
+    }
+
+    #[test]
+    fn platform() {
+        let text = format!("[rom.{}]\nplatform = \"schip\"\n", HASH);
+        let config = parse(&text).unwrap();
+        assert_eq!(config.rom(HASH).unwrap().0.quirks, Quirks::profile("schip"));
+
+        // Quirks given alongside the platform win
+        let text = format!("[rom.{}]\nplatform = \"schip\"\nquirks = \"vip\"\n", HASH);
+        let config = parse(&text).unwrap();
+        assert_eq!(config.rom(HASH).unwrap().0.quirks, Quirks::profile("vip"));
+    }
+
+    #[test]
+    fn comments_and_strings() {
+        let text = format!("# Settings for everything\n\
+                            ips = 700  # Faster than the default\n\
+                            \n\
+                            [rom.{}]  # Some Game\n\
+                            title = 'C:\\games\\#1'\n\
+                            author = \"Some \\\"One\\\" \\u00e9\\\\\" # Who\n\
+                            keys = \"#5 fires\"\n", HASH);
+        let config = parse(&text).unwrap();
+        assert_eq!(config.global().ips, Some(700));
+
+        let info = config.rom(HASH).unwrap().1;
+        assert_eq!(info.title.as_deref(), Some("C:\\games\\#1"));
+        assert_eq!(info.author.as_deref(), Some("Some \"One\" \u{e9}\\"));
+        assert_eq!(info.keys.as_deref(), Some("#5 fires"));
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(parse_string("\"a\\tb\""), Some(String::from("a\tb")));
+        assert_eq!(parse_string("\"\\U0001F600\""), Some(String::from("\u{1F600}")));
+        assert_eq!(parse_string("'a\\tb'"), Some(String::from("a\\tb")));
+        assert_eq!(parse_string("\"\""), Some(String::new()));
+
+        assert_eq!(parse_string("amber"), None);
+        assert_eq!(parse_string("\"a\"b\""), None);
+        assert_eq!(parse_string("'a'b'"), None);
+        assert_eq!(parse_string("\"a\\qb\""), None);
+        assert_eq!(parse_string("\"\\u12\""), None);
+        assert_eq!(parse_string("\"\\uD800\""), None);
+        assert_eq!(parse_string("\"a\\\""), None);
+        assert_eq!(parse_string("'"), None);
+    }
+
+    #[test]
+    fn later_sections_win() {
+        let text = format!("[rom.{0}]\nips = 500\n[rom.{0}]\nips = 900\n", HASH);
+        let config = parse(&text).unwrap();
+        assert_eq!(config.rom(HASH).unwrap().0.ips, Some(900));
+    }
+
+    #[test]
+    fn merge() {
+        let mut settings = Settings { ips: Some(700), ..Settings::default() };
+        settings.merge(&Settings {
+            quirks: Quirks::profile("schip"),
+            ..Settings::default()
+        });
+        assert_eq!(settings.ips, Some(700));
+        assert_eq!(settings.quirks, Quirks::profile("schip"));
+
+        settings.merge(&Settings { ips: Some(1000), ..Settings::default() });
+        assert_eq!(settings.ips, Some(1000));
+        assert_eq!(settings.quirks, Quirks::profile("schip"));
+    }
+
+    #[test]
+    fn errors() {
+        let error = |text: &str| parse(text).err().unwrap();
+
+        assert_eq!(error("ips = 700\nips = 0"),
+                   "test.toml line 2: ips takes a whole number above zero");
+        assert_eq!(error("\n# comment\nquirks = vip"),
+                   "test.toml line 3: quirks takes a string in quotes");
+        assert!(error("quirks = \"nonsense\"")
+                .starts_with("test.toml line 1: Unknown quirk profile \"nonsense\""));
+        assert!(error("platform = \"nonsense\"")
+                .starts_with("test.toml line 1: Unknown platform \"nonsense\""));
+        assert_eq!(error("[rom.1234]"),
+                   "test.toml line 1: Expected [rom.<sha1>], found [rom.1234]");
+        assert_eq!(error("[global]"),
+                   "test.toml line 1: Expected [rom.<sha1>], found [global]");
+        assert_eq!(error("ips"),
+                   "test.toml line 1: Expected key = value, found ips");
+        assert_eq!(error("speed = 2"),
+                   "test.toml line 1: Unknown setting \"speed\"");
+        assert_eq!(error("year = \"1991\""),
+                   "test.toml line 1: year takes a whole number");
+    }
+
+    #[test]
+    fn database() {
+        // The bundled database must at least parse
+        let db = Config::database();
+        assert!(db.global().ips.is_none());
+    }
+
+    #[test]
+    fn hash() {
+        assert_eq!(rom_hash(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(rom_hash(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+    }
+}
@@ -0,0 +1,164 @@
+//! Which parts of a ROM ran as code or were drawn as sprites.
+
+use std::io;
+use std::io::prelude::*;
+use std::ops::Range;
+
+use labels::Labels;
+use parsing::Instruction;
+
+/// Coverage tracks which addresses were executed as code and which bytes
+/// were read as sprite data by DRW, so a session can be checked against
+/// the whole of a ROM.
+pub struct Coverage {
+    exec_counts: Vec<u64>,
+    sprite_counts: Vec<u64>,
+}
+
+// One line of the annotated listing. hits is None for lines that are
+// not instructions (labels and data), which lcov does not count.
+struct ListingLine {
+    text: String,
+    hits: Option<u64>,
+}
+
+impl Coverage {
+    /// Coverage of an empty session.
+    pub fn new() -> Coverage {
+        Coverage {
+            exec_counts: vec![0; 4096],
+            sprite_counts: vec![0; 4096],
+        }
+    }
+
+    /// Record an instruction executed at pc.
+    pub fn record_exec(&mut self, pc: u16) {
+        self.exec_counts[pc as usize] += 1;
+    }
+
+    /// Record a sprite of n bytes read from addr.
+    pub fn record_sprite(&mut self, addr: u16, n: u8) {
+        for i in 0..(n as usize) {
+            let a = addr as usize + i;
+            if a < self.sprite_counts.len() {
+                self.sprite_counts[a] += 1;
+            }
+        }
+    }
+
+    // Build the annotated listing of memory[range].
+    // Bytes executed as code are listed as instructions with their hit
+    // counts, bytes read as sprites as data with theirs. Anything else that
+    // decodes is assumed to be code that never ran and marked #####.
+    fn listing(&self, memory: &[u8], range: Range<usize>,
+               labels: &Labels) -> Vec<ListingLine> {
+        let mut lines = Vec::new();
+        let mut addr = range.start;
+        let end = range.end;
+
+        while addr < end {
+            if let Some(label) = labels.get(addr as u16) {
+                lines.push(ListingLine { text: format!("{}:", label), hits: None });
+            }
+
+            let ins = if addr + 1 < end {
+                Instruction::from_slice_one(&memory[addr..(addr + 2)])
+            } else {
+                None
+            };
+            let sprite = self.sprite_counts[addr];
+            let exec = self.exec_counts[addr];
+
+            // Code that starts on the next byte takes precedence, since
+            // instructions are not always aligned
+            let next_exec = addr + 1 < end && self.exec_counts[addr + 1] > 0;
+
+            match ins {
+                Some(ins) if exec > 0 || (sprite == 0 && !next_exec) => {
+                    let count = if exec > 0 {
+                        format!("{:>9}", exec)
+                    } else {
+                        String::from("    #####")
+                    };
+                    lines.push(ListingLine {
+                        text: format!("{}:  {:03X}  {:02X}{:02X}  {}",
+                                      count, addr, memory[addr], memory[addr + 1], ins),
+                        hits: Some(exec),
+                    });
+                    addr += 2;
+                },
+                _ => {
+                    let count = if sprite > 0 {
+                        format!("{:>9}", sprite)
+                    } else {
+                        String::from("        -")
+                    };
+                    lines.push(ListingLine {
+                        text: format!("{}:  {:03X}  {:02X}    .byte {:08b}",
+                                      count, addr, memory[addr], memory[addr]),
+                        hits: None,
+                    });
+                    addr += 1;
+                },
+            }
+        }
+
+        lines
+    }
+
+    /// Write the annotated disassembly of `memory[range]` to listing
+    /// and an lcov tracefile describing it to lcov. The tracefile's line
+    /// numbers refer to the listing, which is named listing_name in it.
+    pub fn write_reports<W: Write, L: Write>(&self, listing: &mut W, lcov: &mut L,
+                                             listing_name: &str, memory: &[u8],
+                                             range: Range<usize>,
+                                             labels: &Labels) -> io::Result<()> {
+        let lines = self.listing(memory, range, labels);
+
+        writeln!(lcov, "TN:")?;
+        writeln!(lcov, "SF:{}", listing_name)?;
+
+        let mut found = 0;
+        let mut hit = 0;
+        for (i, line) in lines.iter().enumerate() {
+            writeln!(listing, "{}", line.text)?;
+
+            if let Some(hits) = line.hits {
+                writeln!(lcov, "DA:{},{}", i + 1, hits)?;
+                found += 1;
+                if hits > 0 {
+                    hit += 1;
+                }
+            }
+        }
+
+        writeln!(lcov, "LF:{}", found)?;
+        writeln!(lcov, "LH:{}", hit)?;
+        writeln!(lcov, "end_of_record")?;
+
+        Ok(())
+    }
+
+    /// Print a short summary of instructions and sprite bytes covered.
+    pub fn write_summary<W: Write>(&self, out: &mut W, memory: &[u8],
+                                   range: Range<usize>) -> io::Result<()> {
+        let labels = Labels::new();
+        let lines = self.listing(memory, range.clone(), &labels);
+
+        let found = lines.iter().filter(|l| l.hits.is_some()).count();
+        let hit = lines.iter().filter(|l| l.hits.unwrap_or(0) > 0).count();
+        let sprite_bytes = range.filter(|&a| self.sprite_counts[a] > 0).count();
+
+        let pct = if found == 0 { 0.0 } else { 100.0 * hit as f64 / found as f64 };
+        writeln!(out, "Code: {} of {} instructions executed ({:.1}%)", hit, found, pct)?;
+        writeln!(out, "Sprite data: {} bytes read", sprite_bytes)?;
+
+        Ok(())
+    }
+}
+
+impl Default for Coverage {
+    fn default() -> Coverage {
+        Coverage::new()
+    }
+}
@@ -1,15 +1,31 @@
+//! The CHIP8 machine: memory, registers, timers and the instruction set.
+
 use std::fs::File;
 use std::io::prelude::*;
-use std::io::ErrorKind;
-use std::{thread, time};
+use std::io;
+use std::io::{BufWriter, ErrorKind};
+use std::ops::Range;
+use std::thread;
+use std::time::{Duration, Instant};
 use rand;
-use rand::Rng;
 
-use graphics::{Graphics, DrawResult};
+use frontend::{Frontend, Status, RecordRequest};
+use keymap::Hotkey;
+use screen::{DrawResult, SCREEN_WIDTH, SCREEN_HEIGHT};
+use palette::Palette;
+use capture;
+use recorder::Recorder;
 use parsing::Instruction;
-
-// A CPUState struct represents the internal state of a Chip8 CPU.
-// It includes a Graphics struct implemented in graphics.rs.
+use profiler::Profiler;
+use coverage::Coverage;
+use movie::Movie;
+use random::{Random, RandomKind};
+use quirks::Quirks;
+use config::{self, Config};
+
+/// A CPUState struct represents the internal state of a Chip8 CPU.
+/// It draws to and reads keys from a Frontend, such as the SDL Graphics
+/// struct implemented in graphics.rs.
 #[allow(non_snake_case)]
 pub struct CPUState {
     V: [u8; 16],        // General purpose registers: V0, V1, ..., V15
@@ -17,23 +33,109 @@ pub struct CPUState {
     pc: u16,            // Program counter (pc)
 
     memory: [u8; 4096], // 4K memory
+    rom_len: usize,     // Size of the ROM loaded at load_address
+    rom_hash: String,   // SHA-1 of the ROM, in hex
+    load_address: u16,  // Where the ROM was loaded and started from
 
-    graphics: Graphics, // Graphics struct
+    frontend: Box<dyn Frontend>, // Display and keypad
+    keys: [bool; 16],            // Keypad as of the start of the frame
+    held_key: Option<u8>,        // Pressed during FX0A, which waits for its release
+    quirks: Quirks,
 
     delay_timer: u8,
     sound_timer: u8,    // Sound and delay timers
 
     stack: [u16; 16],   // Call stack
     sp: u16,            // Call stack pointer
+
+    ips: u32,           // Instructions per second of emulated time
+    tick_phase: u32,    // Progress toward the next timer tick, in 60ths of an instruction
+    instructions: u64,  // Executed so far
+    frames: u64,        // Timer ticks so far
+
+    paused: bool,       // Run control, from the frontend's hotkeys
+    advance: bool,      // Run one frame while paused
+    speed: usize,       // Index into SPEEDS
+    fast_forward: bool, // Run uncapped, regardless of speed
+    unthrottled: bool,  // Never wait for real time, e.g. for tests
+
+    seed: u64,          // What rng started from
+    rng: Random,        // For RND
+    movie: Option<Movie>, // Being recorded or played back
+    recorder: Option<Recorder>, // Screen recording in progress, if any
+    beeped: bool,       // The sound timer was active this frame, for recordings
+
+    frame_limit: Option<u64>, // Stop running after this many frames
+    trace: Option<BufWriter<File>>, // Every instruction executed is written here
+
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
 }
 
+/// How executing an instruction went.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub enum ExecResult <'a> {
+pub enum ExecResult {
+    /// The instruction ran
     Success,
-    Fail(&'a str),
+    /// The instruction couldn't run, for the given reason
+    Fail(&'static str),
+    /// The program returned from its outermost routine
     Exit,
 }
 
+/// What one step of the machine did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Step {
+    /// How the instruction went
+    pub result: ExecResult,
+    /// Where the instruction was fetched from
+    pub addr: u16,
+    /// None if it didn't decode
+    pub instruction: Option<Instruction>,
+    /// FX0A is waiting for a key, and will run again
+    pub waiting: bool,
+    /// The screen was drawn to or cleared
+    pub drew: bool,
+    /// Timer ticks, that is frames of emulated time, that passed
+    pub ticks: u32,
+}
+
+/// An UndoRecord holds everything one instruction changed, so that it can be
+/// reverted by CPUState::undo. Each entry stores the value from _before_ the
+/// instruction executed.
+#[allow(non_snake_case)]
+#[derive(Clone, Debug, Default)]
+pub struct UndoRecord {
+    pc: u16,
+    I: u16,
+    sp: u16,
+    delay_timer: u8,
+    sound_timer: u8,
+    tick_phase: u32,
+    instructions: u64,
+    frames: u64,
+    rng: Option<Random>, // Always saved; None only in Default
+    held_key: Option<u8>,
+
+    regs: Vec<(u8, u8)>,       // (register, old value)
+    memory: Vec<(u16, u8)>,    // (address, old byte)
+    stack: Vec<(u8, u16)>,     // (stack slot, old value)
+    pixels: Vec<(usize, bool)>,// (screen index, old pixel)
+}
+
+/// Instructions per second, unless set otherwise. The timers tick 60 times
+/// for every second's worth of instructions.
+pub const DEFAULT_IPS: u32 = 600;
+
+// A run that falls further behind real time than this stops trying to
+// catch up, rather than running flat out until it has.
+const MAX_LAG_FRAMES: u32 = 4;
+
+// Emulation speeds to step through, as multiples of real time. Zero runs
+// as fast as possible.
+static SPEEDS: [f32; 7] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0, 0.0];
+const NORMAL_SPEED: usize = 2;
+
 static CHIP8_FONTSET: [u8; 80] =
 [
   0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -55,70 +157,108 @@ static CHIP8_FONTSET: [u8; 80] =
 ];
 
 impl CPUState {
-    pub fn new() -> CPUState {
+    /// A machine with empty memory, showing its screen on frontend.
+    pub fn new(frontend: Box<dyn Frontend>) -> CPUState {
         let mut s = CPUState {
             V: [0; 16],
             I: 0,
             pc: 0x200, // PC starts at 0x200
 
             memory: [0; 4096],
+            rom_len: 0,
+            rom_hash: String::new(),
+            load_address: 0x200,
 
-            graphics: Graphics::new(),
+            frontend: frontend,
+            keys: [false; 16],
+            held_key: None,
+            quirks: Quirks::default(),
 
             delay_timer: 0,
             sound_timer: 0, // Initially in-active
 
             stack: [0; 16],
             sp: 0,
+
+            ips: DEFAULT_IPS,
+            tick_phase: 0,
+            instructions: 0,
+            frames: 0,
+
+            paused: false,
+            advance: false,
+            speed: NORMAL_SPEED,
+            fast_forward: false,
+            unthrottled: false,
+
+            seed: 0,
+            rng: Random::new(RandomKind::Xorshift, 0),
+            movie: None,
+            recorder: None,
+            beeped: false,
+
+            frame_limit: None,
+            trace: None,
+
+            profiler: None,
+            coverage: None,
         };
 
         for i in 0..80 {
             s.memory[i] = CHIP8_FONTSET[i]; // Fill in fontset
         }
+        s.set_random(RandomKind::Xorshift, rand::random());
 
         s
     }
 
-    // Load a ROM from fname into memory starting at 0x200.
-    pub fn load_rom(&mut self, fname: &str) -> Result<(), &str> {
-        let mut f = match File::open(fname) {
-            Ok(f) => f,
-            Err(e) => {
-                match e.kind() {
-                    ErrorKind::NotFound => return Err("File not found"),
-                    _ => return Err("I/O Error opening"),
-                }
-            },
-        };
+    /// Copy a ROM read with read_rom into memory at addr, and start running
+    /// from there. A ROM in the bundled database gets the quirks and speed
+    /// it lists.
+    pub fn load_rom(&mut self, rom: &[u8], addr: u16) {
+        let start = addr as usize;
+        self.memory[start..(start + rom.len())].copy_from_slice(rom);
+        self.rom_len = rom.len();
+        self.rom_hash = config::rom_hash(rom);
+        self.load_address = addr;
+        self.pc = addr;
+        self.apply_rom_settings(&Config::database());
+    }
 
-        let mut buffer = [0; 128];
-        let mut i = 0x200;
-        let mut read = 1;
-
-        while read > 0 {
-            read = match f.read(&mut buffer) {
-                Ok(read) => read,
-                Err(_) => return Err("I/O Error reading"),
-            };
-            if (i + read) > 0xFFF {
-                // Too many bytes
-                return Err("Too many bytes");
-            }
-            for j in 0..read {
-                self.memory[i + j] = buffer[j];
-            }
-            i += read;
+    /// Take the quirks and speed config gives for the loaded ROM, if it has
+    /// a section for it.
+    pub fn apply_rom_settings(&mut self, config: &Config) {
+        let settings = match config.rom(&self.rom_hash) {
+            Some((settings, _)) => settings.clone(),
+            None => return,
+        };
+        if let Some(quirks) = settings.quirks {
+            self.set_quirks(quirks);
+        }
+        if let Some(ips) = settings.ips {
+            self.set_ips(ips);
         }
+    }
 
-        Ok(())
+    /// The SHA-1 of the loaded ROM in hex, which the config and the ROM
+    /// database know it by.
+    pub fn rom_hash(&self) -> &str {
+        &self.rom_hash
     }
 
-    fn valid_addr(&self, addr: u16) -> bool {
-        addr <= 0xFFF
+    // Where n bytes from addr sit in memory, or None if they run past its
+    // end. Worked out in usize, as I and PC can be as high as 0xFFFF.
+    fn mem_range(&self, addr: u16, n: usize) -> Option<Range<usize>> {
+        let start = addr as usize;
+        if start + n <= self.memory.len() {
+            Some(start..(start + n))
+        } else {
+            None
+        }
     }
 
     fn valid_pc(&self, addr: u16) -> bool {
-        addr >= 0x200 && addr <= 0xFFF
+        addr >= self.load_address && addr <= 0xFFF
     }
 
     fn valid_reg(&self, vx: u8) -> bool {
@@ -137,7 +277,7 @@ impl CPUState {
     }
 
     fn clear_op(&mut self) -> ExecResult {
-        self.graphics.clear();
+        self.frontend.screen_mut().clear();
 
         ExecResult::Success
     }
@@ -150,8 +290,11 @@ impl CPUState {
         let x = self.V[vx as usize];
         let y = self.V[vy as usize];
 
-        let mem = &self.memory[(self.I as usize)..(self.I as usize + n as usize)];
-        match self.graphics.draw_sprite(x, y, mem) {
+        let mem = match self.mem_range(self.I, n as usize) {
+            Some(range) => &self.memory[range],
+            None => return ExecResult::Fail("Invalid sprite addr"),
+        };
+        match self.frontend.screen_mut().draw_sprite(x, y, mem, self.quirks.clip) {
             DrawResult::Collision => self.V[0xF] = 1,
             DrawResult::Success   => self.V[0xF] = 0,
         };
@@ -215,7 +358,7 @@ impl CPUState {
             return ExecResult::Fail("Invalid register");
         }
 
-        self.V[vx as usize] += byte;
+        self.V[vx as usize] = self.V[vx as usize].wrapping_add(byte);
 
         ExecResult::Success
     }
@@ -256,14 +399,31 @@ impl CPUState {
         ExecResult::Success
     }
 
-    fn arith_op(&mut self, vx: u8, vy: u8,
-                arith: fn(u8, u8) -> u8) -> ExecResult {
+    fn logic_op(&mut self, vx: u8, vy: u8,
+                logic: fn(u8, u8) -> u8) -> ExecResult {
+        if !self.valid_reg(vx) || !self.valid_reg(vy) {
+            return ExecResult::Fail("Invalid register(s)");
+        }
+
+        let res = logic(self.V[vx as usize], self.V[vy as usize]);
+        self.V[vx as usize] = res;
+        if self.quirks.vf_reset {
+            self.V[0xF] = 0;
+        }
+
+        ExecResult::Success
+    }
+
+    // Shift one bit left or right, leaving the bit shifted out in VF.
+    fn shift_op(&mut self, vx: u8, vy: u8, left: bool) -> ExecResult {
         if !self.valid_reg(vx) || !self.valid_reg(vy) {
             return ExecResult::Fail("Invalid register(s)");
         }
 
-        let res = arith(self.V[vx as usize], self.V[vy as usize]);
+        let src = if self.quirks.shift_vy { self.V[vy as usize] } else { self.V[vx as usize] };
+        let (res, flag) = if left { (src << 1, src >> 7) } else { (src >> 1, src & 1) };
         self.V[vx as usize] = res;
+        self.V[0xF] = flag;
 
         ExecResult::Success
     }
@@ -284,7 +444,7 @@ impl CPUState {
             self.V[0xF] = 0;
         }
 
-        self.V[vx as usize] = arg1 + arg2;
+        self.V[vx as usize] = arg1.wrapping_add(arg2);
 
         ExecResult::Success
     }
@@ -304,7 +464,7 @@ impl CPUState {
             self.V[0xF] = 0;
         }
 
-        self.V[vx as usize] = arg1 - arg2;
+        self.V[vx as usize] = arg1.wrapping_sub(arg2);
 
         ExecResult::Success
     }
@@ -316,7 +476,9 @@ impl CPUState {
     }
 
     fn jumpv0_op(&mut self, addr: u16) -> ExecResult {
-        let dest = (self.V[0] as u16) + addr;
+        // BXNN adds VX, X being the top digit of the address
+        let vx = if self.quirks.jump_vx { (addr >> 8) & 0xF } else { 0 };
+        let dest = (self.V[vx as usize] as u16) + addr;
 
         if !self.valid_pc(dest) {
             return ExecResult::Fail("Invalid jump destination");
@@ -332,7 +494,7 @@ impl CPUState {
             return ExecResult::Fail("Invalid register");
         }
 
-        let val: u8 = rand::thread_rng().gen();
+        let val = self.rng.next(&self.memory);
 
         self.V[vx as usize] = val & byte;
 
@@ -344,9 +506,10 @@ impl CPUState {
             return ExecResult::Fail("Invalid register");
         }
 
-        let x = self.V[vx as usize];
+        // Only the low digit names a key
+        let x = self.V[vx as usize] & 0xF;
 
-        if self.graphics.keys[x as usize] == down {
+        if self.keys[x as usize] == down {
             self.pc += 2;
         }
         
@@ -368,14 +531,28 @@ impl CPUState {
             return ExecResult::Fail("Invalid register");
         }
 
-        let mut k = self.active_key(&(self.graphics.keys));
-        
-        while k == None {
-            self.graphics.draw_events();
-            k = self.active_key(&(self.graphics.keys));
-        }
+        // Until a key is pressed (or released, with the key_release quirk)
+        // this instruction runs again and again, while the timers and the
+        // screen carry on as normal
+        let k = match self.held_key {
+            Some(k) if self.keys[k as usize] => None,
+            Some(k) => Some(k),
+            None => match self.active_key(&self.keys) {
+                Some(k) if self.quirks.key_release => {
+                    self.held_key = Some(k);
+                    None
+                },
+                k => k,
+            },
+        };
 
-        self.V[vx as usize] = k.unwrap();
+        match k {
+            Some(k) => {
+                self.held_key = None;
+                self.V[vx as usize] = k;
+            },
+            None => self.pc -= 2,
+        }
 
         ExecResult::Success
     }
@@ -405,7 +582,7 @@ impl CPUState {
             return ExecResult::Fail("Invalid register");
         }
 
-        self.I += self.V[vx as usize] as u16;
+        self.I = self.I.wrapping_add(self.V[vx as usize] as u16);
 
         ExecResult::Success
     }
@@ -424,9 +601,10 @@ impl CPUState {
         if !self.valid_reg(vx) {
             return ExecResult::Fail("Invalid register");
         }
-        if !self.valid_addr(self.I + 2) {
-            return ExecResult::Fail("Invalid destination addr");
-        }
+        let dest = match self.mem_range(self.I, 3) {
+            Some(range) => range.start,
+            None => return ExecResult::Fail("Invalid destination addr"),
+        };
 
         let mut val = self.V[vx as usize];
 
@@ -436,9 +614,9 @@ impl CPUState {
         val %= 10;
         let ones = val;
 
-        self.memory[self.I as usize] = hundreds;
-        self.memory[(self.I + 1) as usize] = tens;
-        self.memory[(self.I + 2) as usize] = ones;
+        self.memory[dest] = hundreds;
+        self.memory[dest + 1] = tens;
+        self.memory[dest + 2] = ones;
 
         ExecResult::Success
     }
@@ -447,12 +625,14 @@ impl CPUState {
         if !self.valid_reg(vx) {
             return ExecResult::Fail("Invalid register");
         }
-        if !self.valid_addr(self.I + vx as u16) {
-            return ExecResult::Fail("Invalid destination addr");
-        }
+        let dest = match self.mem_range(self.I, vx as usize + 1) {
+            Some(range) => range,
+            None => return ExecResult::Fail("Invalid destination addr"),
+        };
 
-        for i in 0..(vx + 1) {
-            self.memory[(self.I + (i as u16)) as usize] = self.V[i as usize];
+        self.memory[dest.clone()].copy_from_slice(&self.V[..dest.len()]);
+        if self.quirks.load_store_i {
+            self.I += vx as u16 + 1;
         }
 
         ExecResult::Success
@@ -462,12 +642,14 @@ impl CPUState {
         if !self.valid_reg(vx) {
             return ExecResult::Fail("Invalid register");
         }
-        if !self.valid_addr(self.I + vx as u16) {
-            return ExecResult::Fail("Invalid destination addr");
-        }
+        let src = match self.mem_range(self.I, vx as usize + 1) {
+            Some(range) => range,
+            None => return ExecResult::Fail("Invalid source addr"),
+        };
 
-        for i in 0..(vx + 1) {
-            self.V[i as usize] = self.memory[(self.I + i as u16) as usize];
+        self.V[..src.len()].copy_from_slice(&self.memory[src]);
+        if self.quirks.load_store_i {
+            self.I += vx as u16 + 1;
         }
 
         ExecResult::Success
@@ -492,14 +674,14 @@ impl CPUState {
             &LdV(vx, byte)  => self.loadv_op(vx, byte),
             &AddV(vx, byte) => self.addv_op(vx, byte),
             &Ld(vx, vy)     => self.load_op(vx, vy),
-            &Or(vx, vy)     => self.arith_op(vx, vy, |a, b| a | b),
-            &And(vx, vy)    => self.arith_op(vx, vy, |a, b| a & b),
-            &Xor(vx, vy)    => self.arith_op(vx, vy, |a, b| a ^ b),
+            &Or(vx, vy)     => self.logic_op(vx, vy, |a, b| a | b),
+            &And(vx, vy)    => self.logic_op(vx, vy, |a, b| a & b),
+            &Xor(vx, vy)    => self.logic_op(vx, vy, |a, b| a ^ b),
             &Add(vx, vy)    => self.add_op(vx, vy),
             &Sub(vx, vy)    => self.sub_op(vx, vy),
-            &Shr(vx)        => self.arith_op(vx, vx, |a, _| a >> 1),
+            &Shr(vx, vy)    => self.shift_op(vx, vy, false),
             &Subn(vx, vy)   => self.sub_op(vy, vx),
-            &Shl(vx)        => self.arith_op(vx, vx, |a, _| a << 1),
+            &Shl(vx, vy)    => self.shift_op(vx, vy, true),
             &LdI(addr)      => self.loadi_op(addr),
             &JpV0(addr)     => self.jumpv0_op(addr),
             &Rnd(vx, byte)  => self.rand_op(vx, byte),
@@ -518,8 +700,8 @@ impl CPUState {
         }
     }
 
-    // Print registers (for debug purposes)
-    fn print_regs(&self) {
+    /// Print registers (for debug purposes)
+    pub fn print_regs(&self) {
         print!("pc = {}, ", self.pc);
         for i in 0..16 {
             print!("V{} = {}, ", i, self.V[i]);
@@ -527,43 +709,968 @@ impl CPUState {
         println!("");
     }
 
-    // Run starting at PC (initially 0x200)
-    pub fn run(&mut self) {
-        'main: loop {
-            self.graphics.draw_events();
+    /// The program counter.
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// Jump to pc. Addresses wrap at 4K.
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc & 0xFFF;
+    }
+
+    /// Register Vx. Only the low digit of vx names the register.
+    pub fn v(&self, vx: u8) -> u8 {
+        self.V[(vx & 0xF) as usize]
+    }
+
+    /// Set register Vx. Only the low digit of vx names the register.
+    pub fn set_v(&mut self, vx: u8, val: u8) {
+        self.V[(vx & 0xF) as usize] = val;
+    }
 
-            let ins = 
-            {
-                let memslice = &(self.memory)[(self.pc as usize)..(self.pc as usize + 2)];
+    /// The I register.
+    pub fn i(&self) -> u16 {
+        self.I
+    }
+
+    /// Set the I register. Addresses wrap at 4K.
+    pub fn set_i(&mut self, i: u16) {
+        self.I = i & 0xFFF;
+    }
+
+    /// The stack pointer, the number of active return addresses.
+    pub fn sp(&self) -> u16 {
+        self.sp
+    }
+
+    /// The active part of the call stack, oldest return address first.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..(self.sp as usize)]
+    }
+
+    /// The delay timer.
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
 
-                match Instruction::from_slice_one(memslice) {
-                    Some(ins) => ins,
-                    None => {println!("Invalid instruction {:?}", memslice); break 'main;},
+    /// Set the delay timer.
+    pub fn set_delay_timer(&mut self, val: u8) {
+        self.delay_timer = val;
+    }
+
+    /// The sound timer.
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    /// Set the sound timer.
+    pub fn set_sound_timer(&mut self, val: u8) {
+        self.sound_timer = val;
+    }
+
+    /// Whether the buzzer is sounding, as it does while the sound timer is
+    /// above zero.
+    pub fn sound_active(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    /// All 4K of memory.
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Read a byte of memory. Addresses wrap at 4K.
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.memory[(addr & 0xFFF) as usize]
+    }
+
+    /// Write a byte of memory. Addresses wrap at 4K.
+    pub fn poke(&mut self, addr: u16, val: u8) {
+        self.memory[(addr & 0xFFF) as usize] = val;
+    }
+
+    /// The screen, one bool per pixel in row-major order, 64 wide.
+    pub fn framebuffer(&self) -> &[bool] {
+        self.frontend.screen().pixels()
+    }
+
+    /// Save the screen as a PNG in the palette's colors, each pixel scaled to
+    /// scale x scale.
+    pub fn save_screenshot(&self, fname: &str, scale: u32, palette: &Palette) -> io::Result<()> {
+        capture::save_screenshot(fname, self.framebuffer(), SCREEN_WIDTH as usize,
+                                 SCREEN_HEIGHT as usize, scale as usize, palette)
+    }
+
+    /// Pressed state of each CHIP8 key, as the instructions see it.
+    pub fn keys(&self) -> &[bool; 16] {
+        &self.keys
+    }
+
+    /// Press or release key k (0 to F) for the instructions that follow.
+    /// For hosts that drive the machine with step or run_frame and read the
+    /// keys themselves; run and latch_keys take them from the frontend,
+    /// replacing any set here.
+    pub fn set_key(&mut self, k: u8, down: bool) {
+        self.keys[(k & 0xF) as usize] = down;
+    }
+
+    /// Take the keys for the next frame from the frontend, recording them
+    /// if a movie is being recorded, or else from the movie being played
+    /// back. Once playback is over, the keys come from the frontend again.
+    pub fn latch_keys(&mut self) {
+        self.keys = *self.frontend.keys();
+
+        let mut over = false;
+        if let Some(ref mut movie) = self.movie {
+            if movie.is_playing() {
+                match movie.next_frame() {
+                    Some(keys) => self.keys = keys,
+                    None => over = true,
                 }
-            };
+            } else {
+                movie.record(&self.keys);
+            }
+        }
+        if over {
+            self.movie = None;
+        }
+    }
 
-            self.pc += 2;
-            
-            match self.exec_op(&ins).clone() {
-                ExecResult::Fail(e) =>
-                    {
-                        println!("Error {:?}", e);
-                        println!("Instruction: {:?}", &ins);
-                        break 'main;
-                    },
-                ExecResult::Exit => break 'main,
-                ExecResult::Success => (),
+    /// Restart random numbers for RND from seed, with the given generator.
+    pub fn set_random(&mut self, kind: RandomKind, seed: u64) {
+        self.seed = seed;
+        self.rng = Random::new(kind, seed);
+    }
+
+    /// Start recording a movie of this run, from the current seed. Call
+    /// before running anything.
+    pub fn record_movie(&mut self) {
+        let (kind, seed) = (self.rng.kind(), self.seed);
+        self.set_random(kind, seed);
+        self.movie = Some(Movie::new(kind, seed, self.ips, self.quirks));
+    }
+
+    /// Play a movie back in place of the frontend's keys, with the seed and
+    /// settings it was recorded with. Call before running anything.
+    pub fn play_movie(&mut self, movie: Movie) {
+        self.set_random(movie.random, movie.seed);
+        self.set_ips(movie.ips);
+        self.quirks = movie.quirks;
+        self.movie = Some(movie);
+    }
+
+    /// The movie being recorded or played back, if it isn't over.
+    pub fn take_movie(&mut self) -> Option<Movie> {
+        self.movie.take()
+    }
+
+    /// Record the screen and sound from now on, a frame for every frame of
+    /// emulated time. A recording already in progress is saved first.
+    pub fn start_recording(&mut self, recorder: Recorder) {
+        self.stop_recording();
+        self.frontend.notify(&format!("Recording to {}", recorder.path()));
+        self.recorder = Some(recorder);
+    }
+
+    /// Finish and save the recording in progress, if any.
+    pub fn stop_recording(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            let fname = recorder.path().to_string();
+            match recorder.finish() {
+                Ok(()) => self.frontend.notify(&format!("Saved recording {}", fname)),
+                Err(e) => self.frontend.notify(&format!("Error saving recording {}: {}", fname, e)),
+            }
+        }
+    }
+
+    /// Whether the screen is being recorded.
+    pub fn recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /// Start counting executed instructions for a profile report.
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::new(self.pc));
+    }
+
+    /// The profile gathered so far, if profiling.
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Start tracking which addresses are executed and read as sprites.
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::new());
+    }
+
+    /// The coverage gathered so far, if tracking it.
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Where the ROM was loaded, and execution started.
+    pub fn load_address(&self) -> u16 {
+        self.load_address
+    }
+
+    /// The loaded ROM as it currently sits in memory.
+    pub fn rom(&self) -> &[u8] {
+        let start = self.load_address as usize;
+        &self.memory[start..(start + self.rom_len)]
+    }
+
+    /// The machine's state for the frontend to show.
+    pub fn status(&self, paused: bool) -> Status {
+        Status {
+            instructions: self.instructions,
+            frames: self.frames,
+            paused: paused,
+            speed: self.speed(),
+            ips: self.ips,
+            quirks: self.quirks.name,
+            recording: self.recorder.is_some(),
+        }
+    }
+
+    /// Change the quirks instructions run with.
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// Change how many instructions run each second of emulated time.
+    pub fn set_ips(&mut self, ips: u32) {
+        self.ips = ips.max(1);
+        self.tick_phase = self.tick_phase.min(self.ips - 1);
+    }
+
+    /// Frames of emulated time run so far, that is timer ticks.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Stop running after this many frames, or never with None.
+    pub fn set_frame_limit(&mut self, frames: Option<u64>) {
+        self.frame_limit = frames;
+    }
+
+    /// Write each instruction as it is executed to fname, with the
+    /// registers from before it ran.
+    pub fn enable_trace(&mut self, fname: &str) -> io::Result<()> {
+        self.trace = Some(BufWriter::new(File::create(fname)?));
+        Ok(())
+    }
+
+    /// Run as fast as possible instead of in real time, e.g. for tests.
+    pub fn set_unthrottled(&mut self, unthrottled: bool) {
+        self.unthrottled = unthrottled;
+    }
+
+    /// The emulation speed as a multiple of real time, or zero if uncapped.
+    pub fn speed(&self) -> f32 {
+        if self.fast_forward || self.unthrottled {
+            0.0
+        } else {
+            SPEEDS[self.speed]
+        }
+    }
+
+    // Apply a run control hotkey.
+    fn command(&mut self, hotkey: Hotkey) {
+        match hotkey {
+            Hotkey::Pause => self.paused = !self.paused,
+            Hotkey::Advance => {
+                // Pause first if running
+                if self.paused {
+                    self.advance = true;
+                } else {
+                    self.paused = true;
+                }
+            },
+            Hotkey::FastForward => self.fast_forward = !self.fast_forward,
+            Hotkey::Slower => self.speed = self.speed.saturating_sub(1),
+            Hotkey::Faster => self.speed = (self.speed + 1).min(SPEEDS.len() - 1),
+            // A movie only replays at the rate it was recorded at
+            Hotkey::IpfDown | Hotkey::IpfUp if self.movie.is_some() => (),
+
+            // One instruction more or less per frame
+            Hotkey::IpfDown => {
+                let ips = self.ips.saturating_sub(60).max(60);
+                self.set_ips(ips);
+            },
+            Hotkey::IpfUp => {
+                let ips = self.ips + 60;
+                self.set_ips(ips);
+            },
+            _ => (),
+        }
+    }
+
+    /// The frontend showing this machine.
+    pub fn frontend(&mut self) -> &mut dyn Frontend {
+        &mut *self.frontend
+    }
+
+    /// Decode the instruction at PC without executing it.
+    pub fn current_instruction(&self) -> Option<Instruction> {
+        let range = self.mem_range(self.pc, 2)?;
+        Instruction::from_slice_one(&self.memory[range])
+    }
+
+    /// Fetch, decode and execute one instruction, then tick the timers, and
+    /// say what happened.
+    pub fn step(&mut self) -> Step {
+        let addr = self.pc;
+        let frames = self.frames;
+        let ins = self.current_instruction();
+
+        let res = self.execute(addr, ins);
+        let ran = res == ExecResult::Success;
+
+        Step {
+            result: res,
+            addr: addr,
+            instruction: ins,
+            waiting: ran && self.pc == addr && matches!(ins, Some(Instruction::LdK(_))),
+            drew: ran && matches!(ins, Some(Instruction::Cls) | Some(Instruction::Drw(..))),
+            ticks: (self.frames - frames) as u32,
+        }
+    }
+
+    // Execute ins, the instruction at addr (None if it didn't decode).
+    fn execute(&mut self, addr: u16, ins: Option<Instruction>) -> ExecResult {
+        self.pc = self.pc.wrapping_add(2);
+
+        let ins = match ins {
+            Some(ins) => ins,
+            None => return ExecResult::Fail("Invalid instruction"),
+        };
+
+        if self.trace.is_some() {
+            self.write_trace(addr, &ins);
+        }
+
+        let res = self.exec_op(&ins);
+
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.record(addr, &ins, res);
+        }
+        if let Some(ref mut coverage) = self.coverage {
+            coverage.record_exec(addr);
+            if let Instruction::Drw(_, _, n) = ins {
+                coverage.record_sprite(self.I, n);
             }
+        }
+
+        if res != ExecResult::Success {
+            return res;
+        }
+        self.instructions += 1;
 
+        if self.sound_timer > 0 {
+            self.frontend.beep();
+            self.beeped = true;
+        }
+
+        // The timers tick at 60 Hz of emulated time: every instruction
+        // takes 1/ips seconds, that is 60/ips ticks
+        self.tick_phase += 60;
+        while self.tick_phase >= self.ips {
+            self.tick_phase -= self.ips;
+            self.frames += 1;
+            if let Some(ref mut profiler) = self.profiler {
+                profiler.frame_end();
+            }
+            if let Some(ref mut recorder) = self.recorder {
+                recorder.frame(self.frontend.screen().pixels(), self.beeped);
+            }
+            self.beeped = false;
             if self.delay_timer > 0 {
                 self.delay_timer -= 1;
             }
             if self.sound_timer > 0 {
-                self.graphics.beep(); // TODO: Implement
                 self.sound_timer -= 1;
             }
+        }
+
+        ExecResult::Success
+    }
+
+    // One line of the trace: address, opcode, instruction, then I, the
+    // timers and V0 to VF.
+    fn write_trace(&mut self, addr: u16, ins: &Instruction) {
+        let opcode = (self.memory[addr as usize] as u16) << 8 | self.memory[addr as usize + 1] as u16;
+        let mut line = format!("{:03X}  {:04X}  {:<18} I={:03X} DT={:02X} ST={:02X} V=",
+                               addr, opcode, ins.to_string(), self.I,
+                               self.delay_timer, self.sound_timer);
+        for v in &self.V {
+            line.push_str(&format!("{:02X}", v));
+        }
+
+        // A trace that can't be written is dropped rather than stopping the run
+        let failed = match self.trace {
+            Some(ref mut trace) => writeln!(trace, "{}", line).is_err(),
+            None => false,
+        };
+        if failed {
+            self.trace = None;
+        }
+    }
+
+    /// Like step, but also return an UndoRecord that reverts the instruction.
+    /// The record is built by diffing the state from before and after, so
+    /// it only holds what actually changed.
+    pub fn step_recorded(&mut self) -> (Step, UndoRecord) {
+        let regs = self.V;
+        let memory = self.memory;
+        let stack = self.stack;
+        let screen = self.frontend.screen().pixels().to_vec();
+
+        let mut rec = UndoRecord {
+            pc: self.pc,
+            I: self.I,
+            sp: self.sp,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            tick_phase: self.tick_phase,
+            instructions: self.instructions,
+            frames: self.frames,
+            rng: Some(self.rng.clone()),
+            held_key: self.held_key,
+            ..Default::default()
+        };
+
+        let step = self.step();
+
+        for i in 0..regs.len() {
+            if regs[i] != self.V[i] {
+                rec.regs.push((i as u8, regs[i]));
+            }
+        }
+        for i in 0..memory.len() {
+            if memory[i] != self.memory[i] {
+                rec.memory.push((i as u16, memory[i]));
+            }
+        }
+        for i in 0..stack.len() {
+            if stack[i] != self.stack[i] {
+                rec.stack.push((i as u8, stack[i]));
+            }
+        }
+        let new_screen = self.frontend.screen().pixels();
+        for i in 0..screen.len() {
+            if screen[i] != new_screen[i] {
+                rec.pixels.push((i, screen[i]));
+            }
+        }
+
+        (step, rec)
+    }
+
+    /// Revert the instruction described by rec.
+    pub fn undo(&mut self, rec: &UndoRecord) {
+        self.pc = rec.pc;
+        self.I = rec.I;
+        self.sp = rec.sp;
+        self.delay_timer = rec.delay_timer;
+        self.sound_timer = rec.sound_timer;
+        self.tick_phase = rec.tick_phase;
+        self.instructions = rec.instructions;
+        self.frames = rec.frames;
+        if let Some(ref rng) = rec.rng {
+            self.rng = rng.clone();
+        }
+        self.held_key = rec.held_key;
+
+        for &(i, val) in &rec.regs {
+            self.V[i as usize] = val;
+        }
+        for &(addr, val) in &rec.memory {
+            self.memory[addr as usize] = val;
+        }
+        for &(i, val) in &rec.stack {
+            self.stack[i as usize] = val;
+        }
+        if !rec.pixels.is_empty() {
+            self.frontend.screen_mut().restore_pixels(&rec.pixels);
+            self.frontend.present();
+        }
+    }
 
-            thread::sleep(time::Duration::from_millis(5));
+    /// Run until the timers tick at the end of the current 60 Hz frame, or
+    /// until an instruction doesn't succeed.
+    pub fn run_frame(&mut self) -> ExecResult {
+        let frame = self.frames;
+
+        loop {
+            let res = self.step().result;
+            if res != ExecResult::Success || self.frames != frame {
+                return res;
+            }
         }
     }
+
+    /// Run starting at PC (initially the load address), a frame at a time,
+    /// at the speed chosen with the frontend's hotkeys, until the frame
+    /// limit if one is set.
+    pub fn run(&mut self) {
+        let mut deadline = Instant::now();
+
+        loop {
+            if self.frame_limit.is_some_and(|n| self.frames >= n) {
+                break;
+            }
+
+            let status = self.status(self.paused);
+            self.frontend.update_status(&status);
+            self.frontend.draw_events();
+            if self.frontend.quit_requested() {
+                break;
+            }
+            for hotkey in self.frontend.take_commands() {
+                self.command(hotkey);
+            }
+            match self.frontend.take_record_request() {
+                Some(RecordRequest::Start(recorder)) => self.start_recording(recorder),
+                Some(RecordRequest::Stop) => self.stop_recording(),
+                None => (),
+            }
+
+            if !self.paused || self.advance {
+                self.advance = false;
+
+                self.latch_keys();
+                match self.run_frame() {
+                    ExecResult::Fail(e) => {
+                        self.print_error(e);
+                        break;
+                    },
+                    ExecResult::Exit => break,
+                    ExecResult::Success => (),
+                }
+            }
+
+            // Paused, keep refreshing at the normal rate
+            let speed = if self.paused { 1.0 } else { self.speed() };
+            wait_frame(&mut deadline, speed);
+        }
+    }
+
+    /// Report a failed instruction along with the state it failed in.
+    pub fn print_error(&mut self, e: &str) {
+        self.frontend.suspend();
+        println!("Error {:?}", e);
+        // PC has already moved past the failing instruction
+        let pc = self.pc.wrapping_sub(2) as usize;
+        if pc + 1 < self.memory.len() {
+            let memslice = &self.memory[pc..(pc + 2)];
+            println!("Instruction: {:?} {:?} at {:#05X}",
+                     Instruction::from_slice_one(memslice), memslice, pc);
+        }
+        self.print_regs();
+    }
+}
+
+/// Read a ROM file, checking that it fits in memory from addr.
+pub fn read_rom(fname: &str, addr: u16) -> Result<Vec<u8>, String> {
+    let mut f = match File::open(fname) {
+        Ok(f) => f,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => {
+            return Err(format!("ROM file {} not found", fname));
+        },
+        Err(e) => return Err(format!("Could not open ROM file {}: {}", fname, e)),
+    };
+
+    let mut rom = Vec::new();
+    if let Err(e) = f.read_to_end(&mut rom) {
+        return Err(format!("Could not read ROM file {}: {}", fname, e));
+    }
+
+    let room = 0x1000 - addr as usize;
+    if rom.is_empty() {
+        return Err(format!("ROM file {} is empty", fname));
+    }
+    if rom.len() > room {
+        return Err(format!("ROM file {} is {} bytes, but only {} fit in memory from {:#05X}",
+                           fname, rom.len(), room, addr));
+    }
+
+    Ok(rom)
+}
+
+/// Wait for the end of a frame run at speed (0 for as fast as possible),
+/// given the deadline the frame before it ended at, and move deadline on.
+///
+/// Each frame has a deadline one frame's length after the one before.
+/// Pacing by deadlines rather than by sleeping, a frame that runs late is
+/// made up for in the next ones, so emulated time doesn't drift from real
+/// time.
+pub fn wait_frame(deadline: &mut Instant, speed: f32) {
+    if speed == 0.0 {
+        *deadline = Instant::now();
+        return;
+    }
+
+    *deadline += Duration::from_secs_f64(1.0 / 60.0 / speed as f64);
+    let now = Instant::now();
+    if now < *deadline {
+        wait_until(*deadline);
+    } else if now - *deadline > Duration::from_secs(1) / 60 * MAX_LAG_FRAMES {
+        *deadline = now;
+    }
+}
+
+// Sleep until the given instant. Sleeps can overshoot by a millisecond or
+// so, which is a lot at 60 Hz, so the last of the wait is spent yielding
+// instead.
+fn wait_until(deadline: Instant) {
+    let spin = Duration::from_millis(2);
+
+    let now = Instant::now();
+    if deadline > now + spin {
+        thread::sleep(deadline - now - spin);
+    }
+    while Instant::now() < deadline {
+        thread::yield_now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use headless::Headless;
+
+    // A machine with program loaded at 0x200.
+    fn machine(program: &[u8]) -> CPUState {
+        let mut cpu = CPUState::new(Box::new(Headless::new()));
+        cpu.load_rom(program, 0x200);
+        cpu
+    }
+
+    // Run n instructions, stopping at the first that doesn't succeed.
+    fn run(cpu: &mut CPUState, n: usize) -> ExecResult {
+        for _ in 0..n {
+            let res = cpu.step().result;
+            if res != ExecResult::Success {
+                return res;
+            }
+        }
+        ExecResult::Success
+    }
+
+    // A program that wraps I round to 0xFFFF with FX1E, then runs last.
+    fn high_i(last: [u8; 2]) -> CPUState {
+        let mut program = vec![0x60, 0xFF];
+        for _ in 0..257 {
+            program.extend_from_slice(&[0xF0, 0x1E]);
+        }
+        program.extend_from_slice(&last);
+
+        let mut cpu = machine(&program);
+        assert_eq!(run(&mut cpu, 258), ExecResult::Success);
+        assert_eq!(cpu.i(), 0xFFFF);
+        cpu
+    }
+
+    #[test]
+    fn memory_past_4k() {
+        assert_eq!(high_i([0xD2, 0x22]).step().result, ExecResult::Fail("Invalid sprite addr"));
+        assert_eq!(high_i([0xD2, 0x20]).step().result, ExecResult::Fail("Invalid sprite addr"));
+        assert_eq!(high_i([0xF0, 0x33]).step().result,
+                   ExecResult::Fail("Invalid destination addr"));
+        assert_eq!(high_i([0xF1, 0x55]).step().result,
+                   ExecResult::Fail("Invalid destination addr"));
+        assert_eq!(high_i([0xF1, 0x65]).step().result, ExecResult::Fail("Invalid source addr"));
+
+        // The last byte of memory is fine, one past it isn't
+        let mut cpu = machine(&[0xAF, 0xFD, 0xF0, 0x33, 0xAF, 0xFE, 0xF0, 0x33]);
+        assert_eq!(run(&mut cpu, 2), ExecResult::Success);
+        assert_eq!(run(&mut cpu, 2), ExecResult::Fail("Invalid destination addr"));
+
+        let mut cpu = machine(&[0xAF, 0xFF, 0xD0, 0x01, 0xD0, 0x02]);
+        assert_eq!(run(&mut cpu, 2), ExecResult::Success);
+        assert_eq!(cpu.step().result, ExecResult::Fail("Invalid sprite addr"));
+    }
+
+    #[test]
+    fn accessors_wrap() {
+        let mut cpu = machine(&[0xF0, 0x33, 0xD0, 0x01]);
+
+        cpu.set_v(0x13, 7);
+        assert_eq!(cpu.v(3), 7);
+        assert_eq!(cpu.v(0x13), 7);
+
+        cpu.set_i(0xFFFF);
+        assert_eq!(cpu.i(), 0xFFF);
+        assert_eq!(cpu.step().result, ExecResult::Fail("Invalid destination addr"));
+        cpu.set_i(0xFFFF);
+        assert_eq!(cpu.step().result, ExecResult::Success);
+
+        cpu.set_pc(0x1200);
+        assert_eq!(cpu.pc(), 0x200);
+        cpu.set_pc(0xFFFF);
+        assert_eq!(cpu.pc(), 0xFFF);
+        assert_eq!(cpu.step().result, ExecResult::Fail("Invalid instruction"));
+    }
+
+    #[test]
+    fn undo_to_start() {
+        let program = [
+            0x60, 0x05, // LD V0, 5
+            0xA3, 0x00, // LD I, 0x300
+            0xF0, 0x33, // LD B, V0
+            0xD0, 0x15, // DRW V0, V1, 5
+            0x22, 0x0C, // CALL 0x20C
+            0x00, 0x00,
+            0x70, 0x01, // ADD V0, 1
+            0xC1, 0xFF, // RND V1, 0xFF
+            0xF0, 0x15, // LD DT, V0
+        ];
+        let mut cpu = machine(&program);
+        cpu.set_ips(60);
+        let regs: Vec<u8> = (0..16).map(|x| cpu.v(x)).collect();
+        let memory = cpu.memory().to_vec();
+        let screen = cpu.framebuffer().to_vec();
+
+        let history: Vec<UndoRecord> = (0..8).map(|_| cpu.step_recorded().1).collect();
+        assert_eq!(cpu.pc(), 0x212);
+        assert_eq!(cpu.sp(), 1);
+        assert!(cpu.memory() != &memory[..]);
+        assert!(cpu.framebuffer() != &screen[..]);
+        let rnd = cpu.v(1);
+
+        for rec in history.iter().rev() {
+            cpu.undo(rec);
+        }
+        assert_eq!((0..16).map(|x| cpu.v(x)).collect::<Vec<u8>>(), regs);
+        assert_eq!(cpu.pc(), 0x200);
+        assert_eq!(cpu.i(), 0);
+        assert_eq!(cpu.sp(), 0);
+        assert_eq!(cpu.delay_timer(), 0);
+        assert_eq!(cpu.memory(), &memory[..]);
+        assert_eq!(cpu.framebuffer(), &screen[..]);
+
+        // Running forward again gives the same random number
+        run(&mut cpu, 7);
+        assert_eq!(cpu.v(1), rnd);
+    }
+
+    // The numbers RND gives with the generator and seed.
+    fn random_numbers(kind: RandomKind, seed: u64) -> Vec<u8> {
+        let program = [0xC0, 0xFF, 0xC1, 0xFF, 0xC2, 0xFF, 0xC3, 0xFF];
+        let mut cpu = machine(&program);
+        cpu.set_random(kind, seed);
+        run(&mut cpu, 4);
+        (0..4).map(|x| cpu.v(x)).collect()
+    }
+
+    #[test]
+    fn seeded_random() {
+        for &kind in &[RandomKind::Xorshift, RandomKind::Vip] {
+            assert_eq!(random_numbers(kind, 0x1234), random_numbers(kind, 0x1234));
+            assert!(random_numbers(kind, 0x1234) != random_numbers(kind, 0x5678));
+        }
+    }
+
+    #[test]
+    fn wait_for_key() {
+        let program = [
+            0x61, 0x30, // LD V1, 0x30
+            0xF1, 0x15, // LD DT, V1
+            0xF0, 0x0A, // LD V0, K
+        ];
+        let mut cpu = machine(&program);
+        cpu.set_ips(60);
+        run(&mut cpu, 2);
+
+        // Nothing pressed: it waits, and the timers keep counting down
+        for _ in 0..3 {
+            assert!(cpu.step().waiting);
+        }
+        assert_eq!(cpu.pc(), 0x204);
+        assert_eq!(cpu.delay_timer(), 0x30 - 4);
+
+        // It takes the key when it's released, not pressed
+        cpu.set_key(7, true);
+        assert!(cpu.step().waiting);
+        assert!(cpu.step().waiting);
+        cpu.set_key(7, false);
+        let step = cpu.step();
+        assert!(!step.waiting);
+        assert_eq!(cpu.v(0), 7);
+        assert_eq!(cpu.pc(), 0x206);
+        assert_eq!(cpu.delay_timer(), 0x30 - 7);
+    }
+
+    #[test]
+    fn wait_for_key_press() {
+        let mut cpu = machine(&[0xF0, 0x0A]);
+        cpu.set_quirks(Quirks::profile("schip").unwrap());
+        assert!(cpu.step().waiting);
+        cpu.set_key(7, true);
+        assert!(!cpu.step().waiting);
+        assert_eq!(cpu.v(0), 7);
+        assert_eq!(cpu.pc(), 0x202);
+    }
+
+    #[test]
+    fn step_result() {
+        let program = [
+            0x60, 0x05, // LD V0, 5
+            0xD0, 0x05, // DRW V0, V0, 5
+            0x00, 0xE0, // CLS
+            0xFF, 0xFF,
+        ];
+        let mut cpu = machine(&program);
+
+        let step = cpu.step();
+        assert_eq!(step.result, ExecResult::Success);
+        assert_eq!(step.addr, 0x200);
+        assert_eq!(step.instruction, Some(Instruction::LdV(0, 5)));
+        assert!(!step.drew);
+        assert!(!step.waiting);
+        assert_eq!(step.ticks, 0);
+
+        let step = cpu.step();
+        assert_eq!(step.addr, 0x202);
+        assert_eq!(step.instruction, Some(Instruction::Drw(0, 0, 5)));
+        assert!(step.drew);
+        assert!(cpu.step().drew);
+
+        let step = cpu.step();
+        assert_eq!(step.result, ExecResult::Fail("Invalid instruction"));
+        assert_eq!(step.addr, 0x206);
+        assert_eq!(step.instruction, None);
+    }
+
+    #[test]
+    fn step_ticks() {
+        // At 600 instructions a second, the timers tick every tenth
+        let mut cpu = machine(&[0x12, 0x00]);
+        let ticks: Vec<u32> = (0..20).map(|_| cpu.step().ticks).collect();
+        assert_eq!(ticks.iter().sum::<u32>(), 2);
+        assert_eq!(cpu.frames(), 2);
+
+        // Below 60 a second, each step takes more than one tick
+        let mut cpu = machine(&[0x12, 0x00]);
+        cpu.set_ips(20);
+        let ticks: Vec<u32> = (0..2).map(|_| cpu.step().ticks).collect();
+        assert_eq!(ticks.iter().sum::<u32>(), 6);
+    }
+
+    #[test]
+    fn run_frame() {
+        let mut cpu = machine(&[0x12, 0x00]);
+        for frame in 1..4 {
+            assert_eq!(cpu.run_frame(), ExecResult::Success);
+            assert_eq!(cpu.frames(), frame);
+            assert_eq!(cpu.status(false).instructions, frame * 10);
+        }
+
+        cpu.set_ips(120);
+        cpu.run_frame();
+        assert_eq!(cpu.status(false).instructions, 32);
+
+        // A failing instruction ends the frame early
+        let mut cpu = machine(&[0x60, 0x01, 0xFF, 0xFF]);
+        assert_eq!(cpu.run_frame(), ExecResult::Fail("Invalid instruction"));
+        assert_eq!(cpu.frames(), 0);
+    }
+
+    // Run each instruction of program once, with the quirks.
+    fn with_quirks(quirks: Quirks, program: &[u8]) -> CPUState {
+        let mut cpu = machine(program);
+        cpu.set_quirks(quirks);
+        assert_eq!(run(&mut cpu, program.len() / 2), ExecResult::Success);
+        cpu
+    }
+
+    #[test]
+    fn quirks() {
+        // key_release is covered by the FX0A tests
+        let q = Quirks::default();
+        assert_eq!(q.name, "classic");
+
+        // 8XY6 shifts VY or VX
+        let program = [0x60, 0x03, 0x61, 0x06, 0x80, 0x16];
+        assert_eq!(with_quirks(q, &program).v(0), 1);
+        assert_eq!(with_quirks(Quirks { shift_vy: true, ..q }, &program).v(0), 3);
+
+        // FX55 and FX65 leave I alone or past the last register
+        let program = [0xA3, 0x00, 0xF1, 0x55, 0xF2, 0x65];
+        assert_eq!(with_quirks(q, &program).i(), 0x300);
+        assert_eq!(with_quirks(Quirks { load_store_i: true, ..q }, &program).i(), 0x305);
+
+        // BXNN adds V0 or VX
+        let program = [0x60, 0x10, 0x63, 0x04, 0xB3, 0x00];
+        assert_eq!(with_quirks(q, &program).pc(), 0x310);
+        assert_eq!(with_quirks(Quirks { jump_vx: true, ..q }, &program).pc(), 0x304);
+
+        // 8XY1 leaves VF or clears it
+        let program = [0x6F, 0x05, 0x60, 0x01, 0x61, 0x02, 0x80, 0x11];
+        assert_eq!(with_quirks(q, &program).v(0xF), 5);
+        assert_eq!(with_quirks(Quirks { vf_reset: true, ..q }, &program).v(0xF), 0);
+
+        // A sprite at the right edge wraps to the left, or is cut off. The
+        // top row of the 0 in the font is four pixels wide.
+        let program = [0x60, 0x3E, 0x61, 0x00, 0xA0, 0x00, 0xD0, 0x11];
+        let wrapped = with_quirks(q, &program);
+        assert!(wrapped.framebuffer()[62] && wrapped.framebuffer()[63]);
+        assert!(wrapped.framebuffer()[0] && wrapped.framebuffer()[1]);
+        let clipped = with_quirks(Quirks { clip: true, ..q }, &program);
+        assert!(clipped.framebuffer()[62] && clipped.framebuffer()[63]);
+        assert!(!clipped.framebuffer()[0] && !clipped.framebuffer()[1]);
+    }
+
+    #[test]
+    fn undo_counters() {
+        // A frame per instruction
+        let mut cpu = machine(&[0x12, 0x00]);
+        cpu.set_ips(60);
+
+        let history: Vec<UndoRecord> = (0..20).map(|_| cpu.step_recorded().1).collect();
+        assert_eq!(cpu.frames(), 20);
+        assert_eq!(cpu.status(false).instructions, 20);
+
+        for rec in history.iter().rev() {
+            cpu.undo(rec);
+        }
+        assert_eq!(cpu.frames(), 0);
+        assert_eq!(cpu.status(false).instructions, 0);
+    }
+
+    #[test]
+    fn pc_past_4k() {
+        // Jump to the last instruction in memory, a SYS, and run off the end
+        let mut cpu = machine(&[0x1F, 0xFE]);
+        assert_eq!(run(&mut cpu, 2), ExecResult::Success);
+        assert_eq!(cpu.pc(), 0x1000);
+        assert_eq!(cpu.current_instruction(), None);
+
+        for _ in 0..3 {
+            let step = cpu.step();
+            assert_eq!(step.result, ExecResult::Fail("Invalid instruction"));
+            assert_eq!(step.instruction, None);
+        }
+    }
+
+    #[test]
+    fn rom_settings() {
+        let program = [0x12, 0x00];
+        let text = format!("[rom.{}]\nplatform = \"schip\"\nips = 1000\n",
+                           config::rom_hash(&program));
+        let db = Config::parse(&text, "test.toml", ::std::path::Path::new("")).unwrap();
+
+        let mut cpu = machine(&program);
+        assert_eq!(cpu.rom_hash(), config::rom_hash(&program));
+        cpu.apply_rom_settings(&db);
+        assert_eq!(cpu.status(false).quirks, "schip");
+        assert_eq!(cpu.status(false).ips, 1000);
+
+        // Another ROM keeps the defaults
+        let mut cpu = machine(&[0x12, 0x02]);
+        cpu.apply_rom_settings(&db);
+        assert_eq!(cpu.status(false).quirks, "classic");
+        assert_eq!(cpu.status(false).ips, DEFAULT_IPS);
+    }
 }
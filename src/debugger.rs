@@ -0,0 +1,478 @@
+//! Breakpoints, watches and stepping, forwards and backwards.
+
+use std::collections::VecDeque;
+use std::io;
+use std::io::prelude::*;
+use std::time::Instant;
+
+use cpu::{CPUState, ExecResult, UndoRecord, wait_frame};
+#[cfg(feature = "sdl")]
+use debugview::{DebugView, DebugAction};
+use expr::Expr;
+
+// How many undo records are kept before the oldest ones are dropped.
+const HISTORY_LIMIT: usize = 100000;
+
+/// A Debugger drives a CPUState one instruction at a time.
+/// Every instruction executed under the debugger leaves an UndoRecord
+/// behind, so execution can also be walked backwards.
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    watches: Vec<Expr>,
+    history: VecDeque<UndoRecord>,
+}
+
+// A Breakpoint stops execution before the instruction at addr runs, or
+// before any instruction if it has no address. A condition limits it to
+// the times the condition holds.
+struct Breakpoint {
+    addr: Option<u16>,
+    cond: Option<Expr>,
+}
+
+/// Why running under the debugger stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// A breakpoint at the given address was hit
+    Breakpoint(u16),
+    /// Execution stopped with a non-Success result
+    Result(ExecResult),
+    /// Nothing left to step back over
+    HistoryStart,
+    /// The requested number of steps was taken
+    Steps,
+    /// The user closed the window
+    Quit,
+}
+
+// Parse an address given as hex, with or without a 0x prefix.
+fn parse_addr(s: &str) -> Option<u16> {
+    let digits = s.trim_start_matches("0x").trim_start_matches("0X");
+
+    match u16::from_str_radix(digits, 16) {
+        Ok(addr) if addr <= 0xFFF => Some(addr),
+        _ => None,
+    }
+}
+
+impl Debugger {
+    /// A debugger with no breakpoints or watches set.
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: Vec::new(),
+            watches: Vec::new(),
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Whether any breakpoint is set at addr, conditional or not.
+    pub fn is_breakpoint(&self, addr: u16) -> bool {
+        self.breakpoints.iter().any(|b| b.addr == Some(addr))
+    }
+
+    /// Whether a breakpoint applies to the current state of cpu.
+    pub fn hit_breakpoint(&self, cpu: &CPUState) -> bool {
+        self.breakpoints.iter().any(|b| {
+            b.addr.is_none_or(|addr| addr == cpu.pc()) &&
+                b.cond.as_ref().is_none_or(|cond| cond.is_true(cpu))
+        })
+    }
+
+    /// Break at addr, or anywhere with None, whenever cond holds, or always
+    /// with None.
+    pub fn add_breakpoint(&mut self, addr: Option<u16>, cond: Option<Expr>) {
+        self.breakpoints.push(Breakpoint { addr, cond });
+    }
+
+    /// Set a breakpoint at addr, or remove the ones there if any are set.
+    /// Returns whether a breakpoint is set afterwards.
+    pub fn toggle_breakpoint(&mut self, addr: u16) -> bool {
+        if self.is_breakpoint(addr) {
+            self.breakpoints.retain(|b| b.addr != Some(addr));
+            false
+        } else {
+            self.add_breakpoint(Some(addr), None);
+            true
+        }
+    }
+
+    /// The expressions shown after every stop.
+    pub fn watches(&self) -> &[Expr] {
+        &self.watches
+    }
+
+    /// Execute one instruction, recording how to undo it.
+    pub fn step(&mut self, cpu: &mut CPUState) -> ExecResult {
+        let (step, rec) = cpu.step_recorded();
+
+        if self.history.len() >= HISTORY_LIMIT {
+            self.history.pop_front();
+        }
+        self.history.push_back(rec);
+
+        step.result
+    }
+
+    /// Undo the most recent instruction.
+    /// Returns false if there is no history left to undo.
+    pub fn step_back(&mut self, cpu: &mut CPUState) -> bool {
+        match self.history.pop_back() {
+            Some(rec) => {
+                cpu.undo(&rec);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Execute up to n instructions, stopping early at a breakpoint or when
+    /// an instruction does not succeed. The instruction at the current pc is
+    /// always executed, even if it has a breakpoint on it.
+    pub fn run(&mut self, cpu: &mut CPUState, n: usize) -> StopReason {
+        for i in 0..n {
+            if i > 0 && self.hit_breakpoint(cpu) {
+                return StopReason::Breakpoint(cpu.pc());
+            }
+
+            match self.step(cpu) {
+                ExecResult::Success => (),
+                res => return StopReason::Result(res),
+            }
+        }
+
+        StopReason::Steps
+    }
+
+    // Execute instructions until the timers tick at the end of the current
+    // frame, stopping early at a breakpoint or when an instruction does not
+    // succeed. Unless check_first is set, the instruction at the current pc
+    // is executed even if it has a breakpoint on it, so that running
+    // resumes from where it stopped.
+    fn run_frame(&mut self, cpu: &mut CPUState, check_first: bool) -> StopReason {
+        let frame = cpu.frames();
+        let mut check = check_first;
+
+        loop {
+            if check && self.hit_breakpoint(cpu) {
+                return StopReason::Breakpoint(cpu.pc());
+            }
+            check = true;
+
+            match self.step(cpu) {
+                ExecResult::Success => (),
+                res => return StopReason::Result(res),
+            }
+            if cpu.frames() != frame {
+                return StopReason::Steps;
+            }
+        }
+    }
+
+    /// Undo up to n instructions, stopping early once pc lands on a breakpoint
+    /// or the recorded history runs out.
+    pub fn run_back(&mut self, cpu: &mut CPUState, n: usize) -> StopReason {
+        for _ in 0..n {
+            if !self.step_back(cpu) {
+                return StopReason::HistoryStart;
+            }
+            if self.hit_breakpoint(cpu) {
+                return StopReason::Breakpoint(cpu.pc());
+            }
+        }
+
+        StopReason::Steps
+    }
+
+    // Run until a breakpoint is hit or execution stops, a frame at a time
+    // at the same pace as CPUState::run.
+    fn cont(&mut self, cpu: &mut CPUState) -> StopReason {
+        let mut deadline = Instant::now();
+        let mut check = false;
+
+        loop {
+            let status = cpu.status(false);
+            cpu.frontend().update_status(&status);
+            cpu.frontend().draw_events();
+            cpu.latch_keys();
+            if cpu.frontend().quit_requested() {
+                return StopReason::Quit;
+            }
+
+            match self.run_frame(cpu, check) {
+                StopReason::Steps => (),
+                reason => return reason,
+            }
+            check = true;
+
+            wait_frame(&mut deadline, cpu.speed());
+        }
+    }
+
+    // Step back until a breakpoint is reached or the history runs out.
+    fn reverse_cont(&mut self, cpu: &mut CPUState) -> StopReason {
+        loop {
+            if !self.step_back(cpu) {
+                return StopReason::HistoryStart;
+            }
+            if self.hit_breakpoint(cpu) {
+                return StopReason::Breakpoint(cpu.pc());
+            }
+        }
+    }
+
+    fn print_stop(&self, cpu: &mut CPUState, reason: StopReason) {
+        cpu.frontend().present();
+        cpu.frontend().suspend(); // A terminal frontend needs the terminal back
+
+        match reason {
+            StopReason::Breakpoint(addr) => println!("Breakpoint at {:#05X}", addr),
+            StopReason::Result(ExecResult::Fail(e)) => cpu.print_error(e),
+            StopReason::Result(ExecResult::Exit) => println!("Program exited"),
+            StopReason::Result(ExecResult::Success) => (),
+            StopReason::HistoryStart => println!("Reached start of recorded history"),
+            StopReason::Steps => (),
+            StopReason::Quit => return,
+        }
+
+        println!("{:#05X}: {:?}", cpu.pc(), cpu.current_instruction());
+        for w in &self.watches {
+            let val = w.eval(cpu);
+            println!("  {} = {} ({:#X})", w, val, val);
+        }
+    }
+
+    fn print_breakpoints(&self) {
+        for (i, b) in self.breakpoints.iter().enumerate() {
+            let addr = match b.addr {
+                Some(addr) => format!("{:#05X}", addr),
+                None => String::from("any"),
+            };
+            match b.cond {
+                Some(ref cond) => println!("{}: {} if {}", i, addr, cond),
+                None => println!("{}: {}", i, addr),
+            }
+        }
+    }
+
+    fn print_help(&self) {
+        println!("s, step [n]       Execute n instructions (default 1)");
+        println!("bs, back [n]      Step back n instructions (default 1)");
+        println!("c, continue       Run until a breakpoint is hit");
+        println!("rc, rcontinue     Run backwards until a breakpoint is hit");
+        println!("b, break <addr>   Toggle a breakpoint at addr (hex)");
+        println!("b <addr> if <e>   Break at addr only when expression e holds");
+        println!("when <e>          Break before any instruction where e holds");
+        println!("l, list           List breakpoints");
+        println!("d, delete <n>     Delete breakpoint number n");
+        println!("w, watch <e>      Print expression e at every stop");
+        println!("uw, unwatch <n>   Remove watch number n");
+        println!("p, print <e>      Evaluate expression e");
+        println!("                  e.g. V3 == 5 && I > 0x300, [I+2] != 0, key(0xA)");
+        println!("r, regs           Print registers");
+        println!("q, quit           Exit the debugger");
+    }
+
+    /// Interactive debugger prompt reading commands from stdin.
+    pub fn console(&mut self, cpu: &mut CPUState) {
+        let stdin = io::stdin();
+
+        println!("{:#05X}: {:?}", cpu.pc(), cpu.current_instruction());
+
+        loop {
+            cpu.frontend().suspend();
+            print!("(chip8) ");
+            io::stdout().flush().unwrap();
+
+            let mut line = String::new();
+            match stdin.lock().read_line(&mut line) {
+                Ok(0) | Err(_) => return,
+                Ok(_) => (),
+            }
+
+            let mut words = line.split_whitespace();
+            let cmd = match words.next() {
+                Some(cmd) => cmd,
+                None => continue,
+            };
+            let arg = words.next();
+            let count = arg.and_then(|a| a.parse::<usize>().ok()).unwrap_or(1);
+            let rest = line.trim()[cmd.len()..].trim();
+
+            match cmd {
+                "s" | "step" => {
+                    let reason = self.run(cpu, count);
+                    self.print_stop(cpu, reason);
+                },
+                "bs" | "back" => {
+                    let reason = self.run_back(cpu, count);
+                    self.print_stop(cpu, reason);
+                },
+                "c" | "continue" => {
+                    let reason = self.cont(cpu);
+                    self.print_stop(cpu, reason);
+                    if reason == StopReason::Quit {
+                        return;
+                    }
+                },
+                "rc" | "rcontinue" => {
+                    let reason = self.reverse_cont(cpu);
+                    self.print_stop(cpu, reason);
+                },
+                "b" | "break" => {
+                    let (addr, cond) = match rest.find(" if ") {
+                        Some(i) => (&rest[..i], Some(&rest[(i + 4)..])),
+                        None => (rest, None),
+                    };
+                    let addr = match parse_addr(addr.trim()) {
+                        Some(addr) => addr,
+                        None => {
+                            println!("Expected an address between 0x000 and 0xFFF");
+                            continue;
+                        },
+                    };
+
+                    match cond.map(Expr::parse) {
+                        Some(Ok(cond)) => {
+                            println!("Breakpoint set at {:#05X} if {}", addr, cond);
+                            self.add_breakpoint(Some(addr), Some(cond));
+                        },
+                        Some(Err(e)) => println!("{}", e),
+                        None => {
+                            if self.toggle_breakpoint(addr) {
+                                println!("Breakpoint set at {:#05X}", addr);
+                            } else {
+                                println!("Breakpoint removed at {:#05X}", addr);
+                            }
+                        },
+                    }
+                },
+                "when" => {
+                    match Expr::parse(rest) {
+                        Ok(cond) => {
+                            println!("Breakpoint set if {}", cond);
+                            self.add_breakpoint(None, Some(cond));
+                        },
+                        Err(e) => println!("{}", e),
+                    }
+                },
+                "l" | "list" => self.print_breakpoints(),
+                "d" | "delete" => {
+                    match arg.and_then(|a| a.parse::<usize>().ok()) {
+                        Some(n) if n < self.breakpoints.len() => {
+                            self.breakpoints.remove(n);
+                        },
+                        _ => println!("No such breakpoint"),
+                    }
+                },
+                "w" | "watch" => {
+                    match Expr::parse(rest) {
+                        Ok(e) => {
+                            let val = e.eval(cpu);
+                            println!("{}: {} = {} ({:#X})", self.watches.len(), e, val, val);
+                            self.watches.push(e);
+                        },
+                        Err(e) => println!("{}", e),
+                    }
+                },
+                "uw" | "unwatch" => {
+                    match arg.and_then(|a| a.parse::<usize>().ok()) {
+                        Some(n) if n < self.watches.len() => {
+                            self.watches.remove(n);
+                        },
+                        _ => println!("No such watch"),
+                    }
+                },
+                "p" | "print" => {
+                    match Expr::parse(rest) {
+                        Ok(e) => {
+                            let val = e.eval(cpu);
+                            println!("{} = {} ({:#X})", e, val, val);
+                        },
+                        Err(e) => println!("{}", e),
+                    }
+                },
+                "r" | "regs" => cpu.print_regs(),
+                "q" | "quit" => return,
+                "h" | "help" => self.print_help(),
+                _ => println!("Unknown command {:?}, try help", cmd),
+            }
+        }
+    }
+
+    /// Graphical debugger: run the machine with a DebugView window open,
+    /// taking commands from that window instead of stdin.
+    #[cfg(feature = "sdl")]
+    pub fn gui(&mut self, cpu: &mut CPUState) {
+        let video = match cpu.frontend().as_graphics() {
+            Some(graphics) => graphics.video(),
+            None => {
+                println!("The graphical debugger needs the SDL frontend");
+                return;
+            },
+        };
+        let mut view = DebugView::new(&video);
+        let mut running = false;
+        let mut check = false; // Whether to check for a breakpoint at pc
+        let mut deadline = Instant::now();
+
+        loop {
+            let status = cpu.status(!running);
+            cpu.frontend().update_status(&status);
+            cpu.frontend().draw_events();
+            cpu.latch_keys();
+            if cpu.frontend().quit_requested() {
+                return;
+            }
+
+            let events = cpu.frontend().as_graphics().unwrap().take_events();
+            for event in events {
+                match view.handle_event(&event) {
+                    Some(DebugAction::ToggleBreakpoint(addr)) => {
+                        self.toggle_breakpoint(addr);
+                    },
+                    Some(DebugAction::RunPause) => {
+                        running = !running;
+                        check = false;
+                    },
+                    Some(DebugAction::Step) => {
+                        running = false;
+                        let reason = self.run(cpu, 1);
+                        self.print_stop(cpu, reason);
+                    },
+                    Some(DebugAction::StepBack) => {
+                        running = false;
+                        self.step_back(cpu);
+                    },
+                    Some(DebugAction::ReverseContinue) => {
+                        running = false;
+                        let reason = self.reverse_cont(cpu);
+                        self.print_stop(cpu, reason);
+                    },
+                    Some(DebugAction::Close) => return,
+                    None => (),
+                }
+            }
+
+            if running {
+                match self.run_frame(cpu, check) {
+                    StopReason::Steps => check = true,
+                    reason => {
+                        running = false;
+                        self.print_stop(cpu, reason);
+                    },
+                }
+            }
+
+            view.draw(cpu, self, running);
+
+            // Paused, keep refreshing at the normal rate
+            wait_frame(&mut deadline, if running { cpu.speed() } else { 1.0 });
+        }
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Debugger {
+        Debugger::new()
+    }
+}
@@ -0,0 +1,235 @@
+use sdl2::VideoSubsystem;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::Color;
+use sdl2::gfx::primitives::DrawRenderer;
+use sdl2::render::WindowCanvas;
+
+use cpu::CPUState;
+use debugger::Debugger;
+use parsing::Instruction;
+
+const VIEW_WIDTH: u32 = 640;
+const VIEW_HEIGHT: u32 = 500;
+
+const LINE_HEIGHT: i16 = 10; // The gfx font is 8x8
+const CHAR_WIDTH: i16 = 8;
+
+const DISASM_X: i16 = 8;
+const DISASM_Y: i16 = 24;
+const DISASM_LINES: u16 = 28;
+
+const REGS_X: i16 = 312;
+const REGS_Y: i16 = 24;
+
+const MEMORY_X: i16 = 8;
+const MEMORY_Y: i16 = 324;
+const MEMORY_ROWS: u16 = 8;
+
+const WATCH_X: i16 = 8;
+const WATCH_Y: i16 = 424;
+const WATCH_LINES: usize = 7;
+
+// CHIP8 keypad layout, as printed on the COSMAC VIP.
+const KEYPAD: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC,
+    0x4, 0x5, 0x6, 0xD,
+    0x7, 0x8, 0x9, 0xE,
+    0xA, 0x0, 0xB, 0xF,
+];
+
+const TEXT: Color = Color { r: 200, g: 200, b: 200, a: 255 };
+const DIM: Color = Color { r: 110, g: 110, b: 110, a: 255 };
+const HIGHLIGHT: Color = Color { r: 60, g: 60, b: 120, a: 255 };
+const BREAKPOINT: Color = Color { r: 220, g: 50, b: 50, a: 255 };
+const PRESSED: Color = Color { r: 80, g: 200, b: 80, a: 255 };
+
+// Actions requested by the user through the debug window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebugAction {
+    ToggleBreakpoint(u16),
+    RunPause,
+    Step,
+    StepBack,
+    ReverseContinue,
+    Close,
+}
+
+// A DebugView is a second SDL window showing the machine state:
+// disassembly around pc, registers, stack, timers, keypad and memory at I.
+pub struct DebugView {
+    canvas: WindowCanvas,
+    disasm_start: u16, // Address of the first disassembly line drawn
+}
+
+impl DebugView {
+    pub fn new(video: &VideoSubsystem) -> DebugView {
+        let window = video.window("CHIP8 Debugger", VIEW_WIDTH, VIEW_HEIGHT)
+            .build()
+            .unwrap();
+
+        DebugView {
+            canvas: window.into_canvas().build().unwrap(),
+            disasm_start: 0x200,
+        }
+    }
+
+    // Translate an event for this window into a DebugAction, if any.
+    pub fn handle_event(&self, event: &Event) -> Option<DebugAction> {
+        let window_id = self.canvas.window().id();
+
+        match *event {
+            Event::Window {window_id: id, win_event: WindowEvent::Close, ..}
+                if id == window_id => Some(DebugAction::Close),
+
+            Event::KeyDown {window_id: id, keycode: Some(keycode), ..}
+                if id == window_id => {
+                match keycode {
+                    Keycode::F5  => Some(DebugAction::RunPause),
+                    Keycode::F10 => Some(DebugAction::Step),
+                    Keycode::F9  => Some(DebugAction::StepBack),
+                    Keycode::F8  => Some(DebugAction::ReverseContinue),
+                    _            => None,
+                }
+            },
+
+            Event::MouseButtonDown {window_id: id, mouse_btn: MouseButton::Left, x, y, ..}
+                if id == window_id => self.disasm_line_at(x, y)
+                    .map(DebugAction::ToggleBreakpoint),
+
+            _ => None,
+        }
+    }
+
+    // Map a click position to the address of the disassembly line under it.
+    fn disasm_line_at(&self, x: i32, y: i32) -> Option<u16> {
+        let line = (y - DISASM_Y as i32) / LINE_HEIGHT as i32;
+
+        if x < DISASM_X as i32 || x >= REGS_X as i32 ||
+           y < DISASM_Y as i32 || line >= DISASM_LINES as i32 {
+            return None;
+        }
+
+        let addr = self.disasm_start + 2 * line as u16;
+        if addr > 0xFFE {
+            return None;
+        }
+
+        Some(addr)
+    }
+
+    fn text(&self, col: i16, row: i16, s: &str, color: Color) {
+        self.canvas.string(col, row, s, color).unwrap();
+    }
+
+    fn draw_disassembly(&mut self, cpu: &CPUState, debugger: &Debugger) {
+        let pc = cpu.pc();
+
+        // Keep pc a few lines from the top, on the same byte alignment
+        let mut start = pc.saturating_sub(8);
+        while start + 2 * DISASM_LINES > 0x1000 {
+            start -= 2;
+        }
+        self.disasm_start = start;
+
+        for line in 0..DISASM_LINES {
+            let addr = start + 2 * line;
+            let y = DISASM_Y + line as i16 * LINE_HEIGHT;
+            let mem = &cpu.memory()[(addr as usize)..(addr as usize + 2)];
+
+            if addr == pc {
+                self.canvas.box_(DISASM_X - 2, y - 1, REGS_X - 16, y + LINE_HEIGHT - 2,
+                                 HIGHLIGHT).unwrap();
+            }
+            if debugger.is_breakpoint(addr) {
+                self.canvas.filled_circle(DISASM_X + 3, y + 3, 3, BREAKPOINT).unwrap();
+            }
+
+            let ins = match Instruction::from_slice_one(mem) {
+                Some(ins) => format!("{}", ins),
+                None => String::from("??"),
+            };
+            let line = format!("{:03X}  {:02X}{:02X}  {}", addr, mem[0], mem[1], ins);
+            self.text(DISASM_X + 2 * CHAR_WIDTH, y, &line, TEXT);
+        }
+    }
+
+    fn draw_registers(&self, cpu: &CPUState) {
+        for vx in 0..16u8 {
+            let x = REGS_X + (vx as i16 / 8) * 12 * CHAR_WIDTH;
+            let y = REGS_Y + (vx as i16 % 8) * LINE_HEIGHT;
+            self.text(x, y, &format!("V{:X} = {:02X}", vx, cpu.v(vx)), TEXT);
+        }
+
+        let y = REGS_Y + 9 * LINE_HEIGHT;
+        self.text(REGS_X, y, &format!("PC = {:03X}", cpu.pc()), TEXT);
+        self.text(REGS_X, y + LINE_HEIGHT, &format!("I  = {:03X}", cpu.i()), TEXT);
+        self.text(REGS_X, y + 2 * LINE_HEIGHT, &format!("DT = {:02X}", cpu.delay_timer()), TEXT);
+        self.text(REGS_X, y + 3 * LINE_HEIGHT, &format!("ST = {:02X}", cpu.sound_timer()), TEXT);
+
+        // Stack, innermost return address first
+        let x = REGS_X + 12 * CHAR_WIDTH;
+        self.text(x, y, &format!("SP = {:X}", cpu.sp()), TEXT);
+        for (i, addr) in cpu.stack().iter().rev().enumerate() {
+            self.text(x, y + (i as i16 + 1) * LINE_HEIGHT, &format!("  {:03X}", addr), DIM);
+        }
+
+        // Keypad
+        let x = REGS_X + 24 * CHAR_WIDTH;
+        let keys = cpu.keys();
+        for (i, &k) in KEYPAD.iter().enumerate() {
+            let kx = x + (i as i16 % 4) * 2 * CHAR_WIDTH;
+            let ky = y + (i as i16 / 4) * LINE_HEIGHT;
+            let color = if keys[k as usize] { PRESSED } else { DIM };
+            self.text(kx, ky, &format!("{:X}", k), color);
+        }
+    }
+
+    fn draw_memory(&self, cpu: &CPUState) {
+        // In usize, as I can be anywhere up to 0xFFFF
+        let start = (cpu.i() & !0xF) as usize;
+        let mem = cpu.memory();
+
+        self.text(MEMORY_X, MEMORY_Y - LINE_HEIGHT, "Memory at I", DIM);
+        for row in 0..MEMORY_ROWS {
+            let addr = start + 16 * row as usize;
+            if addr + 16 > mem.len() {
+                break;
+            }
+
+            let mut line = format!("{:03X} ", addr);
+            for i in 0..16 {
+                line.push_str(&format!(" {:02X}", mem[addr + i]));
+            }
+            self.text(MEMORY_X, MEMORY_Y + row as i16 * LINE_HEIGHT, &line, TEXT);
+        }
+    }
+
+    fn draw_watches(&self, cpu: &CPUState, debugger: &Debugger) {
+        self.text(WATCH_X, WATCH_Y - LINE_HEIGHT, "Watches", DIM);
+        for (i, w) in debugger.watches().iter().take(WATCH_LINES).enumerate() {
+            let val = w.eval(cpu);
+            self.text(WATCH_X, WATCH_Y + i as i16 * LINE_HEIGHT,
+                      &format!("{} = {} ({:#X})", w, val, val), TEXT);
+        }
+    }
+
+    // Redraw the whole window from the current machine state.
+    pub fn draw(&mut self, cpu: &CPUState, debugger: &Debugger, running: bool) {
+        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.canvas.clear();
+
+        let status = if running { "RUNNING" } else { "PAUSED" };
+        self.text(DISASM_X, 8, status, PRESSED);
+        self.text(DISASM_X + 10 * CHAR_WIDTH, 8,
+                  "F5 run/pause  F10 step  F9 back  F8 reverse  click: breakpoint", DIM);
+
+        self.draw_disassembly(cpu, debugger);
+        self.draw_registers(cpu);
+        self.draw_memory(cpu);
+        self.draw_watches(cpu, debugger);
+
+        self.canvas.present();
+    }
+}
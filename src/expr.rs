@@ -0,0 +1,462 @@
+//! Expressions over the machine state, for the debugger.
+
+use std::fmt;
+
+use cpu::CPUState;
+
+/// Expressions used by the debugger for breakpoint conditions and watches,
+/// such as `V3 == 5 && I > 0x300`, `[I+2] != 0`, `sp >= 12` or `key(0xA)`.
+///
+/// Values are integers. Comparisons and logical operators yield 1 or 0,
+/// and any non-zero value counts as true.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+    /// A number
+    Num(u32),
+    /// Register Vx
+    V(u8),
+    /// The I register
+    I,
+    /// The program counter
+    Pc,
+    /// The stack pointer
+    Sp,
+    /// The delay timer
+    Dt,
+    /// The sound timer
+    St,
+    /// `[addr]`: the byte at addr
+    Mem(Box<Expr>),
+    /// key(k): 1 if key k is down
+    Key(Box<Expr>),
+    /// !e: 1 if e is zero, else 0
+    Not(Box<Expr>),
+    /// -e
+    Neg(Box<Expr>),
+    /// An operator applied to two operands
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+/// Operators taking two operands, from lowest to highest precedence.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinOp {
+    /// ||
+    Or,
+    /// &&
+    And,
+    /// ==
+    Eq,
+    /// !=
+    Ne,
+    /// <
+    Lt,
+    /// <=
+    Le,
+    /// >
+    Gt,
+    /// >=
+    Ge,
+    /// |
+    BitOr,
+    /// ^
+    BitXor,
+    /// &
+    BitAnd,
+    /// +
+    Add,
+    /// -
+    Sub,
+    /// *
+    Mul,
+    /// /
+    Div,
+    /// %
+    Mod,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Num(u32),
+    Ident(String),
+    Op(&'static str),
+}
+
+// Operators, longest first so that e.g. "<=" is not read as "<".
+static OPERATORS: [&str; 21] = [
+    "||", "&&", "==", "!=", "<=", ">=",
+    "<", ">", "|", "^", "&", "+", "-", "*", "/", "%", "!", "(", ")", "[", "]",
+];
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+
+    while !rest.is_empty() {
+        let c = rest.chars().next().unwrap();
+
+        if c.is_ascii_digit() {
+            let len = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+            let word = &rest[..len];
+            let num = if word.starts_with("0x") || word.starts_with("0X") {
+                u32::from_str_radix(&word[2..], 16)
+            } else {
+                word.parse::<u32>()
+            };
+
+            match num {
+                Ok(n) => tokens.push(Token::Num(n)),
+                Err(_) => return Err(format!("Invalid number {:?}", word)),
+            }
+            rest = &rest[len..];
+        } else if c.is_ascii_alphabetic() {
+            let len = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..len].to_lowercase()));
+            rest = &rest[len..];
+        } else {
+            match OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+                Some(op) => {
+                    tokens.push(Token::Op(op));
+                    rest = &rest[op.len()..];
+                },
+                None => return Err(format!("Unexpected character {:?}", c)),
+            }
+        }
+
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+// Binary operators grouped by precedence, loosest first.
+static PRECEDENCE: [&[(&str, BinOp)]; 8] = [
+    &[("||", BinOp::Or)],
+    &[("&&", BinOp::And)],
+    &[("==", BinOp::Eq), ("!=", BinOp::Ne), ("<=", BinOp::Le),
+      (">=", BinOp::Ge), ("<", BinOp::Lt), (">", BinOp::Gt)],
+    &[("|", BinOp::BitOr)],
+    &[("^", BinOp::BitXor)],
+    &[("&", BinOp::BitAnd)],
+    &[("+", BinOp::Add), ("-", BinOp::Sub)],
+    &[("*", BinOp::Mul), ("/", BinOp::Div), ("%", BinOp::Mod)],
+];
+
+// A recursive descent parser over the token list.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        t
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Op(o)) if o == op => Ok(()),
+            Some(t) => Err(format!("Expected {:?}, found {:?}", op, t)),
+            None => Err(format!("Expected {:?} at end of expression", op)),
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+
+        let mut lhs = self.binary(level + 1)?;
+
+        loop {
+            let op = match self.peek() {
+                Some(&Token::Op(o)) => PRECEDENCE[level].iter().find(|p| p.0 == o).map(|p| p.1),
+                _ => None,
+            };
+
+            match op {
+                Some(op) => {
+                    self.pos += 1;
+                    let rhs = self.binary(level + 1)?;
+                    lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+                },
+                None => return Ok(lhs),
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some(&Token::Op("!")) => {
+                self.pos += 1;
+                Ok(Expr::Not(Box::new(self.unary()?)))
+            },
+            Some(&Token::Op("-")) => {
+                self.pos += 1;
+                Ok(Expr::Neg(Box::new(self.unary()?)))
+            },
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Num(n)) => Ok(Expr::Num(n)),
+            Some(Token::Op("(")) => {
+                let e = self.binary(0)?;
+                self.expect(")")?;
+                Ok(e)
+            },
+            Some(Token::Op("[")) => {
+                let e = self.binary(0)?;
+                self.expect("]")?;
+                Ok(Expr::Mem(Box::new(e)))
+            },
+            Some(Token::Ident(ref name)) if name == "key" => {
+                self.expect("(")?;
+                let e = self.binary(0)?;
+                self.expect(")")?;
+                Ok(Expr::Key(Box::new(e)))
+            },
+            Some(Token::Ident(name)) => {
+                match name.as_str() {
+                    "i"  => Ok(Expr::I),
+                    "pc" => Ok(Expr::Pc),
+                    "sp" => Ok(Expr::Sp),
+                    "dt" => Ok(Expr::Dt),
+                    "st" => Ok(Expr::St),
+                    _ if name.len() == 2 && name.starts_with('v') => {
+                        match u8::from_str_radix(&name[1..], 16) {
+                            Ok(vx) => Ok(Expr::V(vx)),
+                            Err(_) => Err(format!("Unknown register {:?}", name)),
+                        }
+                    },
+                    _ => Err(format!("Unknown name {:?}", name)),
+                }
+            },
+            Some(t) => Err(format!("Unexpected {:?}", t)),
+            None => Err(String::from("Unexpected end of expression")),
+        }
+    }
+}
+
+impl Expr {
+    /// Parse an expression, such as one typed at the debugger prompt.
+    pub fn parse(s: &str) -> Result<Expr, String> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+        };
+
+        let e = parser.binary(0)?;
+        match parser.next() {
+            None => Ok(e),
+            Some(t) => Err(format!("Unexpected {:?} after expression", t)),
+        }
+    }
+
+    /// Evaluate against the current machine state.
+    pub fn eval(&self, cpu: &CPUState) -> u32 {
+        match *self {
+            Expr::Num(n) => n,
+            Expr::V(vx)  => cpu.v(vx) as u32,
+            Expr::I      => cpu.i() as u32,
+            Expr::Pc     => cpu.pc() as u32,
+            Expr::Sp     => cpu.sp() as u32,
+            Expr::Dt     => cpu.delay_timer() as u32,
+            Expr::St     => cpu.sound_timer() as u32,
+            Expr::Mem(ref addr) => {
+                let mem = cpu.memory();
+                mem[addr.eval(cpu) as usize % mem.len()] as u32
+            },
+            Expr::Key(ref k) => cpu.keys()[(k.eval(cpu) & 0xF) as usize] as u32,
+            Expr::Not(ref e) => (e.eval(cpu) == 0) as u32,
+            Expr::Neg(ref e) => e.eval(cpu).wrapping_neg(),
+            Expr::Binary(op, ref lhs, ref rhs) => {
+                let a = lhs.eval(cpu);
+
+                // Short-circuit the logical operators
+                match op {
+                    BinOp::And if a == 0 => return 0,
+                    BinOp::Or if a != 0 => return 1,
+                    _ => (),
+                }
+
+                let b = rhs.eval(cpu);
+                match op {
+                    BinOp::Or | BinOp::And => (b != 0) as u32,
+                    BinOp::Eq     => (a == b) as u32,
+                    BinOp::Ne     => (a != b) as u32,
+                    BinOp::Lt     => (a < b) as u32,
+                    BinOp::Le     => (a <= b) as u32,
+                    BinOp::Gt     => (a > b) as u32,
+                    BinOp::Ge     => (a >= b) as u32,
+                    BinOp::BitOr  => a | b,
+                    BinOp::BitXor => a ^ b,
+                    BinOp::BitAnd => a & b,
+                    BinOp::Add    => a.wrapping_add(b),
+                    BinOp::Sub    => a.wrapping_sub(b),
+                    BinOp::Mul    => a.wrapping_mul(b),
+                    BinOp::Div    => a.checked_div(b).unwrap_or(0),
+                    BinOp::Mod    => a.checked_rem(b).unwrap_or(0),
+                }
+            },
+        }
+    }
+
+    /// Whether the expression evaluates to non-zero.
+    pub fn is_true(&self, cpu: &CPUState) -> bool {
+        self.eval(cpu) != 0
+    }
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = PRECEDENCE.iter()
+            .flat_map(|level| level.iter())
+            .find(|p| p.1 == *self)
+            .unwrap().0;
+
+        write!(f, "{}", s)
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expr::Num(n) => write!(f, "{:#X}", n),
+            Expr::V(vx)  => write!(f, "V{:X}", vx),
+            Expr::I      => write!(f, "I"),
+            Expr::Pc     => write!(f, "pc"),
+            Expr::Sp     => write!(f, "sp"),
+            Expr::Dt     => write!(f, "dt"),
+            Expr::St     => write!(f, "st"),
+            Expr::Mem(ref e) => write!(f, "[{}]", e),
+            Expr::Key(ref e) => write!(f, "key({})", e),
+            Expr::Not(ref e) => write!(f, "!{}", e),
+            Expr::Neg(ref e) => write!(f, "-{}", e),
+            Expr::Binary(op, ref a, ref b) => write!(f, "({} {} {})", a, op, b),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use headless::Headless;
+
+    fn cpu() -> CPUState {
+        CPUState::new(Box::new(Headless::new()))
+    }
+
+    fn eval(s: &str, cpu: &CPUState) -> u32 {
+        Expr::parse(s).unwrap().eval(cpu)
+    }
+
+    #[test]
+    fn precedence() {
+        let shown = |s: &str| Expr::parse(s).unwrap().to_string();
+
+        assert_eq!(shown("1 + 2 * 3"), "(0x1 + (0x2 * 0x3))");
+        assert_eq!(shown("1 - 2 - 3"), "((0x1 - 0x2) - 0x3)");
+        assert_eq!(shown("v0 == 1 || v1 & 2 != 0 && !i"),
+                   "((V0 == 0x1) || (((V1 & 0x2) != 0x0) && !I))");
+        assert_eq!(shown("(1 + 2) * -3"), "((0x1 + 0x2) * -0x3)");
+        assert_eq!(shown("1 | 2 ^ 3 & 4"), "(0x1 | (0x2 ^ (0x3 & 0x4)))");
+
+        let cpu = cpu();
+        assert_eq!(eval("1 + 2 * 3", &cpu), 7);
+        assert_eq!(eval("(1 + 2) * 3", &cpu), 9);
+        assert_eq!(eval("1 | 2 ^ 3", &cpu), 1);
+        assert_eq!(eval("1 ^ 3 & 2", &cpu), 3);
+        assert_eq!(eval("2 & 3 ^ 1", &cpu), 3);
+        assert_eq!(eval("10 - 4 - 3", &cpu), 3);
+    }
+
+    #[test]
+    fn logical() {
+        let cpu = cpu();
+
+        // Logical operators yield 1 or 0, whatever their operands
+        assert_eq!(eval("5 && 7", &cpu), 1);
+        assert_eq!(eval("5 && 0", &cpu), 0);
+        assert_eq!(eval("0 && 7", &cpu), 0);
+        assert_eq!(eval("5 || 0", &cpu), 1);
+        assert_eq!(eval("0 || 7", &cpu), 1);
+        assert_eq!(eval("0 || 0", &cpu), 0);
+        assert_eq!(eval("!5", &cpu), 0);
+        assert_eq!(eval("!0", &cpu), 1);
+    }
+
+    #[test]
+    fn short_circuit() {
+        let cpu = cpu();
+
+        // The left hand side decides, whatever is on the right
+        assert_eq!(eval("0 && [0xFFFFFFFF] + key(0xFFFF) / 0", &cpu), 0);
+        assert_eq!(eval("1 || [0xFFFFFFFF] + key(0xFFFF) / 0", &cpu), 1);
+        assert_eq!(eval("1 && 0 || 2", &cpu), 1);
+    }
+
+    #[test]
+    fn registers_and_memory() {
+        let mut cpu = cpu();
+        cpu.set_v(3, 5);
+        cpu.set_i(0x300);
+        cpu.poke(0x302, 0xAB);
+
+        assert_eq!(eval("V3 == 5 && I > 0x2FF", &cpu), 1);
+        assert_eq!(eval("[I+2]", &cpu), 0xAB);
+        assert_eq!(eval("[i + 2] != 0", &cpu), 1);
+
+        // Addresses wrap around at the end of memory
+        assert_eq!(eval("[0x1302]", &cpu), 0xAB);
+        assert_eq!(eval("[0x302 - 0x1000]", &cpu), 0xAB);
+    }
+
+    #[test]
+    fn key() {
+        let mut cpu = cpu();
+        cpu.set_key(0xA, true);
+
+        assert_eq!(eval("key(0xA)", &cpu), 1);
+        assert_eq!(eval("key(10)", &cpu), 1);
+        assert_eq!(eval("key(0xB)", &cpu), 0);
+
+        // Only the low four bits pick the key
+        assert_eq!(eval("key(0x1A)", &cpu), 1);
+    }
+
+    #[test]
+    fn division_by_zero() {
+        let mut cpu = cpu();
+        cpu.set_v(0, 0);
+
+        assert_eq!(eval("7 / V0", &cpu), 0);
+        assert_eq!(eval("7 % 0", &cpu), 0);
+        assert_eq!(eval("7 / 2", &cpu), 3);
+        assert_eq!(eval("7 % 2", &cpu), 1);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = |s: &str| Expr::parse(s).unwrap_err();
+
+        assert_eq!(error("0xZZ"), "Invalid number \"0xZZ\"");
+        assert_eq!(error("1 $ 2"), "Unexpected character '$'");
+        assert_eq!(error("(1 + 2"), "Expected \")\" at end of expression");
+        assert_eq!(error("[1 + 2)"), "Expected \"]\", found Op(\")\")");
+        assert_eq!(error("key 1"), "Expected \"(\", found Num(1)");
+        assert_eq!(error("vg"), "Unknown register \"vg\"");
+        assert_eq!(error("foo"), "Unknown name \"foo\"");
+        assert_eq!(error("1 +"), "Unexpected end of expression");
+        assert_eq!(error(""), "Unexpected end of expression");
+        assert_eq!(error("1 2"), "Unexpected Num(2) after expression");
+        assert_eq!(error("* 2"), "Unexpected Op(\"*\")");
+    }
+}
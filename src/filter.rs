@@ -0,0 +1,106 @@
+//! Display filters against the flicker of CHIP8 games, which erase sprites
+//! by XORing them and then draw them again. They only change how the screen
+//! is shown, never the emulated screen itself.
+
+/// How each presented frame is combined with the ones before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Blend {
+    /// Each frame is shown as it is
+    None,
+    /// A pixel is lit if it was lit in either of the last two frames
+    Or,
+    /// Lit pixels fade out over a few frames, like a CRT phosphor
+    Phosphor,
+}
+
+// How much of its brightness a phosphor pixel keeps each frame.
+const PHOSPHOR_DECAY: f32 = 0.55;
+
+// Below this a fading pixel counts as dark.
+const PHOSPHOR_MIN: f32 = 0.02;
+
+/// Filters applied to each frame before it is shown.
+#[derive(Clone)]
+pub struct DisplayFilter {
+    blend: Blend,
+    /// Darken the lower part of each row, for a CRT look
+    pub scanlines: bool,
+
+    prev: Vec<bool>,     // The screen as of the previous frame
+    intensity: Vec<f32>, // Brightness of each pixel as shown, 0 to 1
+    settling: bool,
+}
+
+impl DisplayFilter {
+    /// A filter that shows frames unchanged.
+    pub fn new() -> DisplayFilter {
+        DisplayFilter {
+            blend: Blend::None,
+            scanlines: false,
+
+            prev: Vec::new(),
+            intensity: Vec::new(),
+            settling: false,
+        }
+    }
+
+    /// Parse a comma separated list such as "phosphor,scanlines".
+    pub fn parse(s: &str) -> Result<DisplayFilter, String> {
+        let mut filter = DisplayFilter::new();
+
+        for name in s.split(',') {
+            match name.trim() {
+                "none"      => filter.blend = Blend::None,
+                "or"        => filter.blend = Blend::Or,
+                "phosphor"  => filter.blend = Blend::Phosphor,
+                "scanlines" => filter.scanlines = true,
+                other => return Err(format!("Unknown filter {:?} (filters are none, or, \
+                                             phosphor and scanlines)", other)),
+            }
+        }
+
+        Ok(filter)
+    }
+
+    /// Compute the brightness of every pixel for the next frame shown.
+    pub fn apply(&mut self, screen: &[bool]) -> &[f32] {
+        if self.intensity.len() != screen.len() {
+            self.prev = screen.to_vec();
+            self.intensity = vec![0.0; screen.len()];
+        }
+
+        self.settling = false;
+
+        for i in 0..screen.len() {
+            let lit = if screen[i] { 1.0 } else { 0.0 };
+
+            self.intensity[i] = match self.blend {
+                Blend::None => lit,
+                Blend::Or => if self.prev[i] { 1.0 } else { lit },
+                Blend::Phosphor => {
+                    let faded = self.intensity[i] * PHOSPHOR_DECAY;
+                    if faded > lit && faded >= PHOSPHOR_MIN { faded } else { lit }
+                },
+            };
+
+            if self.intensity[i] != lit || self.prev[i] != screen[i] {
+                self.settling = true;
+            }
+            self.prev[i] = screen[i];
+        }
+
+        &self.intensity
+    }
+
+    /// Whether the image would still change with more frames of an
+    /// unchanged screen, e.g. while pixels fade out.
+    pub fn settling(&self) -> bool {
+        self.settling && self.blend != Blend::None
+    }
+}
+
+impl Default for DisplayFilter {
+    fn default() -> DisplayFilter {
+        DisplayFilter::new()
+    }
+}
@@ -0,0 +1,113 @@
+//! What the CPU needs from whatever shows its screen and reads its keys.
+
+use screen::Screen;
+#[cfg(feature = "sdl")]
+use graphics::Graphics;
+use keymap::Hotkey;
+use recorder::Recorder;
+
+/// What the CPU tells its frontend about the running machine, e.g. for an
+/// on-screen display.
+#[derive(Clone, Debug, Default)]
+pub struct Status {
+    /// Executed since the machine started
+    pub instructions: u64,
+    /// 60 Hz timer ticks since the machine started
+    pub frames: u64,
+    /// Stopped, e.g. in the debugger
+    pub paused: bool,
+    /// Target speed as a multiple of real time, 0 if uncapped
+    pub speed: f32,
+    /// Target instructions per second
+    pub ips: u32,
+    /// Name of the quirk profile
+    pub quirks: &'static str,
+    /// The screen is being recorded
+    pub recording: bool,
+}
+
+/// A recording the user asked the CPU to start or stop, e.g. with the
+/// record hotkey.
+pub enum RecordRequest {
+    /// Start this recording, saving any in progress first
+    Start(Recorder),
+    /// Save and stop the recording in progress
+    Stop,
+}
+
+/// How the screen is scaled to fit the window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScaleMode {
+    /// Largest whole-number scale that fits, centered
+    Integer,
+    /// Largest scale that keeps the 2:1 aspect ratio, centered
+    Aspect,
+    /// Fill the whole window
+    Stretch,
+}
+
+impl ScaleMode {
+    /// Parse a mode by name: "integer", "aspect" or "stretch".
+    pub fn parse(s: &str) -> Option<ScaleMode> {
+        match s {
+            "integer" => Some(ScaleMode::Integer),
+            "aspect"  => Some(ScaleMode::Aspect),
+            "stretch" => Some(ScaleMode::Stretch),
+            _         => None,
+        }
+    }
+}
+
+/// A Frontend shows the CHIP8 screen and supplies the state of the 16-key
+/// pad. The CPU draws into the frontend's Screen and polls it for input;
+/// Graphics does this with an SDL window and Tui in a terminal.
+pub trait Frontend {
+    /// The screen the CPU draws to.
+    fn screen(&self) -> &Screen;
+    /// The screen the CPU draws to, for drawing.
+    fn screen_mut(&mut self) -> &mut Screen;
+
+    /// Pressed state of each CHIP8 key.
+    fn keys(&self) -> &[bool; 16];
+
+    /// Process pending input, then present the screen if a frame is due.
+    fn draw_events(&mut self);
+
+    /// Show the screen as it is now.
+    fn present(&mut self);
+
+    /// Whether the user asked to quit.
+    fn quit_requested(&self) -> bool;
+
+    /// Called for every instruction executed while the sound timer is active.
+    fn beep(&mut self);
+
+    /// Called before draw_events with the latest state of the machine.
+    fn update_status(&mut self, _status: &Status) {}
+
+    /// Run control hotkeys (pause, speed and so on) pressed since the last
+    /// call, for the CPU to act on.
+    fn take_commands(&mut self) -> Vec<Hotkey> {
+        Vec::new()
+    }
+
+    /// A recording asked for since the last call, for the CPU to act on.
+    fn take_record_request(&mut self) -> Option<RecordRequest> {
+        None
+    }
+
+    /// Tell the user something, such as that a recording was saved.
+    fn notify(&mut self, text: &str) {
+        println!("{}", text);
+    }
+
+    /// Give the terminal back for normal output, e.g. before printing an
+    /// error. The frontend takes it again the next time it draws.
+    fn suspend(&mut self) {}
+
+    /// The SDL frontend, for tools that open windows of their own.
+    #[cfg(feature = "sdl")]
+    fn as_graphics(&mut self) -> Option<&mut Graphics> {
+        None
+    }
+}
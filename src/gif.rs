@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::prelude::*;
+
+// A small animated GIF encoder for framebuffer recordings.
+// Frames are given as indices into a palette of up to four colors, and
+// compressed with plain LZW.
+
+// Packs LZW codes least significant bit first into 255-byte sub-blocks.
+struct CodeWriter {
+    bytes: Vec<u8>,
+    acc: u32,
+    nbits: u32,
+}
+
+impl CodeWriter {
+    fn write(&mut self, code: u32, size: u32) {
+        self.acc |= code << self.nbits;
+        self.nbits += size;
+
+        while self.nbits >= 8 {
+            self.bytes.push(self.acc as u8);
+            self.acc >>= 8;
+            self.nbits -= 8;
+        }
+    }
+
+    fn finish<W: Write>(mut self, out: &mut W) -> io::Result<()> {
+        if self.nbits > 0 {
+            self.bytes.push(self.acc as u8);
+        }
+
+        for block in self.bytes.chunks(255) {
+            out.write_all(&[block.len() as u8])?;
+            out.write_all(block)?;
+        }
+        out.write_all(&[0]) // Block terminator
+    }
+}
+
+const MIN_CODE_SIZE: u32 = 2;
+const MAX_CODE: u32 = 4095;
+
+fn write_lzw<W: Write>(out: &mut W, indices: &[u8]) -> io::Result<()> {
+    let clear = 1 << MIN_CODE_SIZE;
+    let end = clear + 1;
+
+    let mut w = CodeWriter { bytes: Vec::new(), acc: 0, nbits: 0 };
+    let mut table: HashMap<(u32, u8), u32> = HashMap::new();
+    let mut next = end + 1;
+    let mut size = MIN_CODE_SIZE + 1;
+
+    out.write_all(&[MIN_CODE_SIZE as u8])?;
+    w.write(clear, size);
+
+    let mut prefix = match indices.first() {
+        Some(&i) => i as u32,
+        None => {
+            w.write(end, size);
+            return w.finish(out);
+        },
+    };
+
+    for &index in &indices[1..] {
+        if let Some(&code) = table.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+
+        w.write(prefix, size);
+
+        if next <= MAX_CODE {
+            table.insert((prefix, index), next);
+            // The decoder grows its code size one code later than we add
+            if next == (1 << size) && size < 12 {
+                size += 1;
+            }
+            next += 1;
+        } else {
+            w.write(clear, size);
+            table.clear();
+            next = end + 1;
+            size = MIN_CODE_SIZE + 1;
+        }
+
+        prefix = index as u32;
+    }
+
+    w.write(prefix, size);
+    w.write(end, size);
+    w.finish(out)
+}
+
+// Write an animated GIF of width x height frames. Each frame is one
+// palette index per pixel plus how many 60ths of a second it is shown.
+// The animation loops forever.
+pub fn write_gif<W: Write>(out: &mut W, width: u16, height: u16, palette: &[[u8; 3]; 4],
+                           frames: &[(Vec<u8>, u32)]) -> io::Result<()> {
+    out.write_all(b"GIF89a")?;
+    out.write_all(&width.to_le_bytes())?;
+    out.write_all(&height.to_le_bytes())?;
+    out.write_all(&[0xF1, 0, 0])?; // Global color table of 4 entries
+    for color in palette {
+        out.write_all(color)?;
+    }
+
+    // NETSCAPE2.0 extension: loop forever
+    out.write_all(&[0x21, 0xFF, 11])?;
+    out.write_all(b"NETSCAPE2.0")?;
+    out.write_all(&[3, 1, 0, 0, 0])?;
+
+    // Delays are in hundredths of a second; carry the rounding error
+    // over so that the total length stays right.
+    let mut elapsed_sixtieths = 0u32;
+    let mut elapsed_hundredths = 0u32;
+
+    for &(ref indices, delay) in frames {
+        elapsed_sixtieths += delay;
+        let until = (elapsed_sixtieths * 100 + 30) / 60;
+        let delay_cs = (until - elapsed_hundredths).min(0xFFFF) as u16;
+        elapsed_hundredths = until;
+
+        // Graphic control extension with the frame delay
+        out.write_all(&[0x21, 0xF9, 4, 0])?;
+        out.write_all(&delay_cs.to_le_bytes())?;
+        out.write_all(&[0, 0])?;
+
+        // Image descriptor covering the whole canvas
+        out.write_all(&[0x2C, 0, 0, 0, 0])?;
+        out.write_all(&width.to_le_bytes())?;
+        out.write_all(&height.to_le_bytes())?;
+        out.write_all(&[0])?;
+
+        write_lzw(out, indices)?;
+    }
+
+    out.write_all(&[0x3B])
+}
@@ -1,176 +1,537 @@
-use std::sync::{Arc, Mutex, Condvar};
-use std::process::exit;
+//! The SDL frontend: a resizable window, keyboard and gamepads.
+
+use std::io;
+use std::mem;
+use std::time::{Duration, Instant};
 
 use sdl2;
-use sdl2::Sdl;
-use sdl2::event::Event;
+use sdl2::{Sdl, VideoSubsystem, GameControllerSubsystem};
+use sdl2::controller::{Axis, GameController};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::hint;
 use sdl2::pixels;
-use sdl2::keyboard::Keycode;
-use sdl2::gfx::primitives::DrawRenderer;
-use sdl2::render::WindowCanvas;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::keyboard::{Keycode, Scancode, LSHIFTMOD, RSHIFTMOD};
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Texture, WindowCanvas};
+use sdl2::video::FullscreenType;
+
+use palette::Palette;
+use filter::DisplayFilter;
+use capture;
+use recorder::Recorder;
+use screen::{Screen, SCREEN_WIDTH, SCREEN_HEIGHT};
+use frontend::{Frontend, Status, ScaleMode, RecordRequest};
+use osd;
+use osd::Osd;
+use keymap::{KeyMap, Binding, Hotkey, GAMEPAD_PREFIX};
 
-const SCREEN_WIDTH: u8 = 64;
-const SCREEN_HEIGHT: u8 = 32;
-const OUTPUT_WIDTH: u32 = 256;
-const OUTPUT_HEIGHT: u32 = 128;
+// The screen is presented at most once per 60 Hz frame. Presents are
+// let through a little early, so that jitter in the CPU's pacing doesn't
+// make them skip frames.
+const FRAME_NANOS: u32 = 1000000000 / 60;
+const PRESENT_GAP_NANOS: u32 = FRAME_NANOS / 4 * 3;
 
+// How far a stick or trigger must move to count as pressed.
+const AXIS_THRESHOLD: i16 = 16384;
+
+/// The SDL frontend: a window showing the screen, and the keyboard and
+/// gamepads for input.
 pub struct Graphics {
     context: Sdl,
+    controller_subsys: Option<GameControllerSubsystem>,
+    controllers: Vec<GameController>, // Open gamepads
+    axes_pressed: Vec<String>,        // Stick and trigger directions held
     canvas: WindowCanvas,
-    screen: [bool; 64 * 32],
-    
-    pub keys: [bool; 16], // Key pressed states
-}
+    texture: Texture, // The screen, uploaded to each time it's presented
+    scale_mode: ScaleMode,
+    palette: Palette,
+    filter: DisplayFilter,
+    keymap: KeyMap,
+    osd: Osd,
+    commands: Vec<Hotkey>, // Run control hotkeys for the CPU
+    capture_prefix: String,   // Start of screenshot file names
+    screen: Screen,
+    last_present: Instant,
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub enum DrawResult {
-    Collision,
-    Success,
+    recording: bool,                       // As of the last status update
+    record_request: Option<RecordRequest>, // From the record hotkey, for the CPU
+
+    /// Key pressed states
+    pub keys: [bool; 16],
+
+    other_events: Vec<Event>, // Events meant for other windows
+    quit: bool,               // Set once the user asked to quit
 }
 
 impl Graphics {
-    // Construct a new Graphics struct.
-    // Initializes sdl2 and defines an sdl context.
-    pub fn new() -> Graphics {
+    /// Construct a new Graphics struct.
+    /// Initializes sdl2 and defines an sdl context. The window starts out
+    /// scale times the size of the CHIP8 screen, and can be resized. With
+    /// vsync, presenting waits for the display's refresh.
+    pub fn new(scale: u32, scale_mode: ScaleMode, fullscreen: bool, vsync: bool) -> Graphics {
         let sdl_context = sdl2::init().unwrap();
         let video_subsys = sdl_context.video().unwrap();
-        let window = video_subsys.window("CHIP8", OUTPUT_WIDTH as u32,
-                                         OUTPUT_HEIGHT as u32)
+
+        // Gamepads are optional; connected ones show up as added devices
+        let controller_subsys = sdl_context.game_controller().ok();
+        let mut window = video_subsys.window("CHIP8", SCREEN_WIDTH as u32 * scale,
+                                             SCREEN_HEIGHT as u32 * scale)
             .position_centered()
+            .resizable()
             .opengl()
             .build()
             .unwrap();
 
-        let mut canvas = window.into_canvas().build().unwrap();
+        if fullscreen {
+            window.set_fullscreen(FullscreenType::Desktop).unwrap();
+        }
+
+        // Scale the screen texture with nearest neighbour to keep it crisp
+        hint::set("SDL_RENDER_SCALE_QUALITY", "0");
+        let mut builder = window.into_canvas();
+        if vsync {
+            builder = builder.present_vsync();
+        }
+        let mut canvas = builder.build().unwrap();
 
         canvas.set_draw_color(pixels::Color::RGB(0, 0, 0));
         canvas.clear();
         canvas.present();
 
+        // Owned by the renderer, which frees it along with itself
+        let texture = canvas.create_texture_streaming(PixelFormatEnum::RGB24,
+                                                      SCREEN_WIDTH as u32,
+                                                      SCREEN_HEIGHT as u32)
+            .unwrap();
+
         Graphics {
             context: sdl_context,
+            controller_subsys: controller_subsys,
+            controllers: Vec::new(),
+            axes_pressed: Vec::new(),
             canvas: canvas,
-            screen: [false; 64 * 32],
+            texture: texture,
+            scale_mode: scale_mode,
+            palette: Palette::classic(),
+            filter: DisplayFilter::new(),
+            keymap: KeyMap::qwerty(),
+            osd: Osd::new(),
+            commands: Vec::new(),
+            capture_prefix: String::from("chip8"),
+            screen: Screen::new(),
+            last_present: Instant::now(),
+
+            recording: false,
+            record_request: None,
 
             keys: [false; 16],
+
+            other_events: Vec::new(),
+            quit: false,
         }
     }
 
-    // Map an SDL Keycode enum to a CHIP8 key, if any.
-    fn key_ind(&self, keycode: Keycode) -> Option<usize> {
-        match keycode {
-            Keycode::Num1 => Some(0x1),
-            Keycode::Num2 => Some(0x2),
-            Keycode::Num3 => Some(0x3),
-            Keycode::Num4 => Some(0xC),
-            Keycode::Q    => Some(0x4),
-            Keycode::W    => Some(0x5),
-            Keycode::E    => Some(0x6),
-            Keycode::R    => Some(0xD),
-            Keycode::A    => Some(0x7),
-            Keycode::S    => Some(0x8),
-            Keycode::D    => Some(0x9),
-            Keycode::F    => Some(0xE),
-            Keycode::Z    => Some(0xA),
-            Keycode::X    => Some(0x0),
-            Keycode::C    => Some(0xB),
-            Keycode::V    => Some(0xF),
-            _             => None,
+    /// The video subsystem, for opening additional windows.
+    pub fn video(&self) -> VideoSubsystem {
+        self.canvas.window().subsystem().clone()
+    }
+
+    /// Take the events draw_events received for windows other than ours.
+    pub fn take_events(&mut self) -> Vec<Event> {
+        mem::take(&mut self.other_events)
+    }
+
+    // The name a key goes by in key maps. Keys SDL has no keycode for,
+    // such as é on AZERTY keyboards, go by the name of their position.
+    fn key_name(keycode: Option<Keycode>, scancode: Option<Scancode>) -> Option<String> {
+        match (keycode, scancode) {
+            (Some(keycode), _) => Some(keycode.name()),
+            (None, Some(scancode)) => Some(scancode.name().to_string()),
+            (None, None) => None,
         }
     }
 
-    // Draw a CHIP8 sprite from a slice to (x, y).
-    // If a collision occurs, return Collision. Otherwise, return Success.
-    pub fn draw_sprite<'a>(&mut self, x: u8, y: u8, slice: &'a [u8]) -> DrawResult {
-        let l = slice.len();
-        let mut collision = false;
-
-        for i in 0..l {
-            for j in 0..8 {
-                let scy = (y as usize + i) % (SCREEN_HEIGHT as usize);
-                let scx = (x as usize + j) % (SCREEN_WIDTH as usize);
-                
-                let scindex = scy * (SCREEN_WIDTH as usize) + scx;
-                let set = (slice[i] >> (7 - j)) & 1;
-                let set_bool =
-                    if set == 1 {
-                        true
-                    } else {
-                        false
-                    };
-
-                if self.screen[scindex] && set_bool {
-                    collision = true;
-                }
+    // Press or release the input called name, per the key map.
+    // Hotkeys fire when pressed, but not again for auto repeats.
+    fn input(&mut self, name: &str, down: bool, shift: bool, repeat: bool) {
+        match self.keymap.binding(name) {
+            Some(Binding::Pad(k)) => self.keys[k as usize] = down,
+            Some(Binding::Hotkey(hotkey)) if down && !repeat => self.hotkey(hotkey, shift),
+            _ => (),
+        }
+    }
 
-                self.screen[scindex] ^= set_bool;
-            }
+    // A stick or trigger moved. Each direction of it is an input of its own,
+    // pressed while the axis is past the threshold that way.
+    fn axis_motion(&mut self, axis: Axis, value: i16) {
+        let name = axis.string();
+        let minus = format!("{}-{}", GAMEPAD_PREFIX, name);
+        let plus = format!("{}+{}", GAMEPAD_PREFIX, name);
+
+        self.axis_direction(minus, value < -AXIS_THRESHOLD);
+        self.axis_direction(plus, value > AXIS_THRESHOLD);
+    }
+
+    // Axes report every small movement, so only changes count as input.
+    fn axis_direction(&mut self, name: String, down: bool) {
+        if down == self.axes_pressed.contains(&name) {
+            return;
         }
-    
-        for i in 0..(SCREEN_WIDTH as usize * SCREEN_HEIGHT as usize) {
-            let cx = (i % (SCREEN_WIDTH as usize)) as i16;
-            let cy = (i / (SCREEN_WIDTH as usize)) as i16;
-            let mut color = pixels::Color::RGB(0, 0, 0);
-
-            if self.screen[i] {
-                color = pixels::Color::RGB(255, 255, 255);
-            }
 
-            for j in (cx*4)..(cx*4 + 4) {
-                for k in (cy*4)..(cy*4 + 4) {
-                    self.canvas.pixel(j, k as i16, color);
+        self.input(&name, down, false, false);
+        if down {
+            self.axes_pressed.push(name);
+        } else {
+            self.axes_pressed.retain(|n| *n != name);
+        }
+    }
+
+    fn open_controller(&mut self, index: u32) {
+        let subsys = match self.controller_subsys {
+            Some(ref subsys) => subsys,
+            None => return,
+        };
+
+        match subsys.open(index) {
+            Ok(controller) => {
+                println!("Gamepad connected: {}", controller.name());
+                self.controllers.push(controller);
+            },
+            Err(e) => println!("Could not open gamepad {}: {}", index, e),
+        }
+    }
+
+    fn hotkey(&mut self, hotkey: Hotkey, shift: bool) {
+        match hotkey {
+            Hotkey::Quit => self.quit = true,
+            Hotkey::Fullscreen => self.toggle_fullscreen(),
+            Hotkey::Screenshot => {
+                // Native resolution, or as shown with shift held
+                let scale = if shift { 0 } else { 1 };
+                match self.screenshot(scale) {
+                    Ok(fname) => self.notify(&format!("Saved screenshot {}", fname)),
+                    Err(e) => self.notify(&format!("Error saving screenshot: {}", e)),
+                }
+            },
+            Hotkey::Record => {
+                if self.recording {
+                    self.record_request = Some(RecordRequest::Stop);
+                } else {
+                    let fname = capture::capture_name(&self.capture_prefix, "gif");
+                    match self.recorder(&fname, shift) {
+                        Ok(recorder) => self.record_request = Some(RecordRequest::Start(recorder)),
+                        Err(e) => self.notify(&e),
+                    }
+                }
+            },
+            Hotkey::Osd => self.osd.toggle(),
+            _ => self.commands.push(hotkey),
+        }
+    }
+
+    // Draw the OSD's lines over the screen area dst, on dark boxes.
+    fn draw_osd(&mut self, dst: Rect) {
+        let unit = (dst.height() / 160).max(2); // Size of a font pixel
+        let line_height = (osd::GLYPH_HEIGHT + 2) * unit;
+
+        let stats = self.osd.stats();
+        let messages = self.osd.messages();
+        let bottom = dst.bottom() - (messages.len() as u32 * line_height) as i32;
+
+        let lines = stats.iter().enumerate()
+            .map(|(i, text)| (dst.y() + (i as u32 * line_height) as i32, text))
+            .chain(messages.iter().enumerate()
+                   .map(|(i, text)| (bottom + (i as u32 * line_height) as i32, text)));
+
+        let mut boxes = Vec::new();
+        let mut dots = Vec::new();
+        for (y, text) in lines {
+            let width = (text.chars().count() as u32 * (osd::GLYPH_WIDTH + 1) + 1) * unit;
+            boxes.push(Rect::new(dst.x(), y, width, line_height));
+
+            for (i, c) in text.chars().enumerate() {
+                let x = dst.x() + ((i as u32 * (osd::GLYPH_WIDTH + 1) + 1) * unit) as i32;
+                for (row, bits) in osd::glyph(c).iter().enumerate() {
+                    for col in 0..osd::GLYPH_WIDTH {
+                        if bits >> (osd::GLYPH_WIDTH - 1 - col) & 1 == 1 {
+                            dots.push(Rect::new(x + (col * unit) as i32,
+                                                y + ((row as u32 + 1) * unit) as i32,
+                                                unit, unit));
+                        }
+                    }
                 }
             }
         }
-        self.canvas.present();
 
-        if collision {
-            DrawResult::Collision
-        } else {
-            DrawResult::Success
+        if boxes.is_empty() {
+            return;
         }
+
+        self.canvas.set_blend_mode(BlendMode::Blend);
+        self.canvas.set_draw_color(pixels::Color::RGBA(0, 0, 0, 160));
+        self.canvas.fill_rects(&boxes).unwrap();
+        self.canvas.set_blend_mode(BlendMode::None);
+        self.canvas.set_draw_color(pixels::Color::RGB(0xFF, 0xFF, 0xFF));
+        self.canvas.fill_rects(&dots).unwrap();
+    }
+
+    // The area of the window the screen is drawn to, per the scale mode.
+    fn screen_rect(&self) -> Rect {
+        let (w, h) = self.canvas.output_size().unwrap();
+        let sw = SCREEN_WIDTH as u32;
+        let sh = SCREEN_HEIGHT as u32;
+
+        let (dw, dh) = match self.scale_mode {
+            ScaleMode::Integer => {
+                let scale = (w / sw).min(h / sh).max(1);
+                (sw * scale, sh * scale)
+            },
+            ScaleMode::Aspect => {
+                if w * sh > h * sw {
+                    (h * sw / sh, h)
+                } else {
+                    (w, w * sh / sw)
+                }
+            },
+            ScaleMode::Stretch => (w, h),
+        };
+
+        Rect::new(((w as i32) - (dw as i32)) / 2, ((h as i32) - (dh as i32)) / 2, dw, dh)
     }
 
-    // Clear the canvas.
-    pub fn clear(&mut self) {
-        for i in 0..self.screen.len() {
-            self.screen[i] = false;
+    // Darken the lower third of every screen row within dst.
+    fn draw_scanlines(&mut self, dst: Rect) {
+        let row = dst.height() / SCREEN_HEIGHT as u32;
+        if row < 3 {
+            return; // Too small to leave anything visible between lines
         }
-        self.canvas.set_draw_color(pixels::Color::RGB(0, 0, 0));
-        self.canvas.clear();
-        self.canvas.present();
+
+        let rects: Vec<Rect> = (0..(SCREEN_HEIGHT as i32))
+            .map(|y| {
+                let top = dst.y() + (y as i64 * dst.height() as i64 / SCREEN_HEIGHT as i64) as i32;
+                Rect::new(dst.x(), top + (row - row / 3) as i32, dst.width(), row / 3)
+            })
+            .collect();
+
+        self.canvas.set_blend_mode(BlendMode::Blend);
+        self.canvas.set_draw_color(pixels::Color::RGBA(0, 0, 0, 110));
+        self.canvas.fill_rects(&rects).unwrap();
+        self.canvas.set_blend_mode(BlendMode::None);
     }
 
-    // Process all queued key events.
-    pub fn draw_events(&mut self) {
+    // Present the screen if it changed and a frame has passed since the
+    // last time, so that drawing many sprites costs one present a frame.
+    // Filters that fade pixels out keep presenting until they settle.
+    fn present_if_due(&mut self) {
+        if (self.screen.is_dirty() || self.filter.settling() || self.osd.active()) &&
+            self.last_present.elapsed() >= Duration::new(0, PRESENT_GAP_NANOS) {
+            self.present();
+        }
+    }
+
+    /// Change the display filters.
+    pub fn set_filter(&mut self, filter: DisplayFilter) {
+        self.filter = filter;
+        self.screen.mark_dirty();
+    }
+
+    /// Screenshots are named after prefix, usually the ROM's name.
+    pub fn set_capture_prefix(&mut self, prefix: &str) {
+        self.capture_prefix = prefix.to_string();
+    }
+
+    /// Save the screen as a PNG in the current palette, each pixel scaled
+    /// to scale x scale.
+    pub fn save_screenshot(&self, fname: &str, scale: u32) -> io::Result<()> {
+        capture::save_screenshot(fname, self.screen.pixels(), SCREEN_WIDTH as usize,
+                                 SCREEN_HEIGHT as usize, scale as usize, &self.palette)
+    }
+
+    /// Save a screenshot named after the capture prefix and the time.
+    /// At scale 0 the screenshot matches the size the screen is shown at.
+    pub fn screenshot(&self, scale: u32) -> io::Result<String> {
+        let scale = if scale == 0 {
+            (self.screen_rect().height() / SCREEN_HEIGHT as u32).max(1)
+        } else {
+            scale
+        };
+        let fname = capture::capture_name(&self.capture_prefix, "png");
+
+        self.save_screenshot(&fname, scale)?;
+        Ok(fname)
+    }
+
+    /// A recorder to fname, a .gif, .png or .apng file, at the size the
+    /// screen is shown at and in the current palette. With raw set, the
+    /// frames are also written as separate PNGs along with a WAV of the
+    /// beeper.
+    pub fn recorder(&self, fname: &str, raw: bool) -> Result<Recorder, String> {
+        let scale = (self.screen_rect().height() / SCREEN_HEIGHT as u32).max(1);
+        Recorder::new(fname, scale, self.palette, raw)
+    }
+
+    /// Change the key bindings.
+    pub fn set_keymap(&mut self, keymap: KeyMap) {
+        self.keymap = keymap;
+    }
+
+    /// Change the display colors.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        self.screen.mark_dirty();
+    }
+
+    /// Switch between windowed and (desktop) fullscreen.
+    pub fn toggle_fullscreen(&mut self) {
+        let window = self.canvas.window_mut();
+        let mode = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _                   => FullscreenType::Off,
+        };
+
+        window.set_fullscreen(mode).unwrap();
+    }
+}
+
+impl Frontend for Graphics {
+    fn screen(&self) -> &Screen {
+        &self.screen
+    }
+
+    fn screen_mut(&mut self) -> &mut Screen {
+        &mut self.screen
+    }
+
+    fn keys(&self) -> &[bool; 16] {
+        &self.keys
+    }
+
+    // Process all queued key events, then present the screen if a frame
+    // is due.
+    fn draw_events(&mut self) {
         let mut events = self.context.event_pump().unwrap();
+        let window_id = self.canvas.window().id();
 
         for event in events.poll_iter() {
+            let target = match event {
+                Event::KeyDown {window_id, ..} => Some(window_id),
+                Event::KeyUp {window_id, ..} => Some(window_id),
+                Event::MouseButtonDown {window_id, ..} => Some(window_id),
+                Event::Window {window_id, ..} => Some(window_id),
+                _ => None,
+            };
+            if target.is_some() && target != Some(window_id) {
+                self.other_events.push(event);
+                continue;
+            }
+
             match event {
-                Event::Quit {..} => exit(0),
+                Event::Quit {..} => self.quit = true,
 
-                Event::KeyDown {keycode: Some(keycode), ..} => {
-                    if keycode == Keycode::Escape {
-                        exit(0);
-                    }
+                Event::Window {win_event: WindowEvent::SizeChanged(..), ..} |
+                Event::Window {win_event: WindowEvent::Exposed, ..} => self.present(),
 
-                    if let Some(ind) = self.key_ind(keycode) {
-                        self.keys[ind as usize] = true;
+                Event::KeyDown {keycode, scancode, keymod, repeat, ..} => {
+                    if let Some(name) = Graphics::key_name(keycode, scancode) {
+                        let shift = keymod.intersects(LSHIFTMOD | RSHIFTMOD);
+                        self.input(&name, true, shift, repeat);
                     }
                 },
-                
-                Event::KeyUp {keycode: Some(keycode), ..} => {
-                    if let Some(ind) = self.key_ind(keycode) {
-                        self.keys[ind as usize] = false;
+
+                Event::KeyUp {keycode, scancode, ..} => {
+                    if let Some(name) = Graphics::key_name(keycode, scancode) {
+                        self.input(&name, false, false, false);
                     }
                 },
 
+                Event::ControllerDeviceAdded {which, ..} => self.open_controller(which),
+
+                Event::ControllerDeviceRemoved {which, ..} => {
+                    self.controllers.retain(|c| c.instance_id() != which);
+                },
+
+                Event::ControllerButtonDown {button, ..} => {
+                    self.input(&format!("{}{}", GAMEPAD_PREFIX, button.string()), true, false, false);
+                },
+
+                Event::ControllerButtonUp {button, ..} => {
+                    self.input(&format!("{}{}", GAMEPAD_PREFIX, button.string()), false, false, false);
+                },
+
+                Event::ControllerAxisMotion {axis, value, ..} => self.axis_motion(axis, value),
+
                 _ => {},
             }
         }
+
+        self.present_if_due();
+    }
+
+    // Upload the screen buffer into a streaming texture and present it,
+    // scaled into the window per the scale mode.
+    fn present(&mut self) {
+        let intensity = self.filter.apply(self.screen.pixels());
+        let bg = self.palette.background();
+        let fg = self.palette.foreground();
+        self.texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
+            for y in 0..(SCREEN_HEIGHT as usize) {
+                for x in 0..(SCREEN_WIDTH as usize) {
+                    let t = intensity[y * (SCREEN_WIDTH as usize) + x];
+                    let offset = y * pitch + x * 3;
+
+                    for c in 0..3 {
+                        let mix = bg[c] as f32 + (fg[c] as f32 - bg[c] as f32) * t;
+                        buffer[offset + c] = mix.round() as u8;
+                    }
+                }
+            }
+        }).unwrap();
+
+        // The letterbox around the screen takes the background color too
+        let dst = self.screen_rect();
+        let [r, g, b] = self.palette.background();
+        self.canvas.set_draw_color(pixels::Color::RGB(r, g, b));
+        self.canvas.clear();
+        self.canvas.copy(&self.texture, None, dst).unwrap();
+        if self.filter.scanlines {
+            self.draw_scanlines(dst);
+        }
+        self.draw_osd(dst);
+        self.canvas.present();
+        self.osd.frame_presented();
+
+        self.screen.mark_presented();
+        self.last_present = Instant::now();
+    }
+
+    // Whether the window was closed or Escape was pressed.
+    fn quit_requested(&self) -> bool {
+        self.quit
+    }
+
+    fn beep(&mut self) {}
+
+    fn update_status(&mut self, status: &Status) {
+        self.osd.update(status);
+        self.recording = status.recording;
+    }
+
+    fn take_record_request(&mut self) -> Option<RecordRequest> {
+        self.record_request.take()
+    }
+
+    // Tell the user something, on stdout and on screen.
+    fn notify(&mut self, text: &str) {
+        println!("{}", text);
+        self.osd.message(text);
+    }
+
+    fn take_commands(&mut self) -> Vec<Hotkey> {
+        mem::take(&mut self.commands)
     }
 
-    pub fn beep(&mut self) {
-        return;
+    fn as_graphics(&mut self) -> Option<&mut Graphics> {
+        Some(self)
     }
 }
@@ -0,0 +1,53 @@
+//! A frontend with nothing to show the screen on.
+
+use screen::Screen;
+use frontend::Frontend;
+
+/// A frontend with no display and no input, for running ROMs from scripts
+/// and tests. Keys can still come from a movie.
+pub struct Headless {
+    screen: Screen,
+    keys: [bool; 16],
+}
+
+impl Headless {
+    /// A frontend with a blank screen and no keys pressed.
+    pub fn new() -> Headless {
+        Headless {
+            screen: Screen::new(),
+            keys: [false; 16],
+        }
+    }
+}
+
+impl Default for Headless {
+    fn default() -> Headless {
+        Headless::new()
+    }
+}
+
+impl Frontend for Headless {
+    fn screen(&self) -> &Screen {
+        &self.screen
+    }
+
+    fn screen_mut(&mut self) -> &mut Screen {
+        &mut self.screen
+    }
+
+    fn keys(&self) -> &[bool; 16] {
+        &self.keys
+    }
+
+    fn draw_events(&mut self) {}
+
+    fn present(&mut self) {
+        self.screen.mark_presented();
+    }
+
+    fn quit_requested(&self) -> bool {
+        false
+    }
+
+    fn beep(&mut self) {}
+}
@@ -0,0 +1,280 @@
+//! A KeyMap binds physical keys, by name, to CHIP8 keys and to emulator
+//! hotkeys. Names are the ones SDL uses, e.g. "Q", "1", "Escape", "F12" or
+//! "Keypad 5"; letters may be given in either case. Any number of names can
+//! be bound to the same CHIP8 key or hotkey, but each name does one thing.
+//!
+//! Gamepad inputs are named "Gamepad " followed by the SDL game controller
+//! name of the button, e.g. "Gamepad a", "Gamepad dpup" or "Gamepad start".
+//! Sticks and triggers count as pressed past half way, in directions named
+//! like "Gamepad -leftx" (left), "Gamepad +lefty" (down) and
+//! "Gamepad +triggerright".
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufReader;
+
+/// Key names for gamepad inputs start with this.
+pub const GAMEPAD_PREFIX: &str = "Gamepad ";
+
+/// The emulator's own functions that keys can be bound to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hotkey {
+    /// Close the emulator
+    Quit,
+    /// Switch between windowed and fullscreen
+    Fullscreen,
+    /// With shift held, at the size shown
+    Screenshot,
+    /// With shift held, also the raw frames and sound
+    Record,
+    /// Show or hide the on-screen statistics
+    Osd,
+
+    // Run control, carried out by the CPU
+    /// Pause or resume
+    Pause,
+    /// Run a single frame, pausing first if running
+    Advance,
+    /// Run as fast as possible, or back at the chosen speed
+    FastForward,
+    /// Run at the next lower speed
+    Slower,
+    /// Run at the next higher speed
+    Faster,
+    /// Fewer instructions per frame
+    IpfDown,
+    /// More instructions per frame
+    IpfUp,
+}
+
+impl Hotkey {
+    /// Whether the CPU rather than the frontend handles this hotkey.
+    pub fn is_run_control(self) -> bool {
+        !matches!(self, Hotkey::Quit | Hotkey::Fullscreen | Hotkey::Screenshot |
+                        Hotkey::Record | Hotkey::Osd)
+    }
+}
+
+static HOTKEYS: [(&str, Hotkey, &str); 12] = [
+    ("quit",       Hotkey::Quit,       "Escape"),
+    ("fullscreen", Hotkey::Fullscreen, "F11"),
+    ("screenshot", Hotkey::Screenshot, "F12"),
+    ("record",     Hotkey::Record,     "F7"),
+    ("osd",        Hotkey::Osd,        "F3"),
+
+    ("pause",        Hotkey::Pause,       "F5"),
+    ("advance",      Hotkey::Advance,     "F6"),
+    ("fast_forward", Hotkey::FastForward, "Tab"),
+    ("slower",       Hotkey::Slower,      "-"),
+    ("faster",       Hotkey::Faster,      "="),
+    ("ipf_down",     Hotkey::IpfDown,     "["),
+    ("ipf_up",       Hotkey::IpfUp,       "]"),
+];
+
+/// What a key does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Binding {
+    /// Press the given CHIP8 key
+    Pad(u8),
+    /// Carry out a hotkey
+    Hotkey(Hotkey),
+}
+
+// Keyboard layouts for the CHIP8 keys 0 to F. Each lays out the pad
+//
+//     1 2 3 C
+//     4 5 6 D
+//     7 8 9 E
+//     A 0 B F
+//
+// on the left-hand 4x4 block of keys.
+static PRESETS: [(&str, [&str; 16]); 3] = [
+    ("qwerty", ["X", "1", "2", "3", "Q", "W", "E", "A",
+                "S", "D", "Z", "C", "4", "R", "F", "V"]),
+    ("azerty", ["X", "1", "2", "3", "A", "Z", "E", "Q",
+                "S", "D", "W", "C", "4", "R", "F", "V"]),
+    ("dvorak", ["Q", "1", "2", "3", "'", ",", ".", "A",
+                "O", "E", ";", "J", "4", "P", "U", "K"]),
+];
+
+// AZERTY keyboards type symbols on the unshifted number row.
+static AZERTY_NUMBERS: [(u8, &str); 3] = [(0x1, "&"), (0x3, "\""), (0xC, "'")];
+
+// Gamepads move with 2, 4, 6 and 8 and fire with 5, which is what most
+// CHIP8 games use.
+static GAMEPAD: [(u8, &str); 9] = [
+    (0x2, "Gamepad dpup"),    (0x2, "Gamepad -lefty"),
+    (0x4, "Gamepad dpleft"),  (0x4, "Gamepad -leftx"),
+    (0x6, "Gamepad dpright"), (0x6, "Gamepad +leftx"),
+    (0x8, "Gamepad dpdown"),  (0x8, "Gamepad +lefty"),
+    (0x5, "Gamepad a"),
+];
+
+fn is_gamepad(name: &str) -> bool {
+    name.get(..GAMEPAD_PREFIX.len()).is_some_and(|p| p.eq_ignore_ascii_case(GAMEPAD_PREFIX))
+}
+
+/// Bindings from key names to CHIP8 keys and hotkeys.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyMap {
+    bindings: Vec<(String, Binding)>,
+}
+
+// Parse a list of quoted key names, e.g. "Q", "Keypad 4".
+fn parse_names(s: &str) -> Result<Vec<String>, String> {
+    let mut names = Vec::new();
+    let mut rest = s.trim();
+
+    while !rest.is_empty() {
+        if !rest.starts_with('"') {
+            return Err(format!("Expected key names in quotes like \"Q\", found {}", s));
+        }
+        let end = match rest[1..].find('"') {
+            Some(i) => i + 1,
+            None => return Err(format!("Unterminated key name in {}", s)),
+        };
+        names.push(rest[1..end].to_string());
+
+        rest = rest[(end + 1)..].trim_start();
+        if rest.starts_with(',') {
+            rest = rest[1..].trim_start();
+        }
+    }
+
+    Ok(names)
+}
+
+impl KeyMap {
+    /// A built-in layout by name, with the default hotkeys.
+    pub fn preset(name: &str) -> Option<KeyMap> {
+        let layout = match PRESETS.iter().find(|p| p.0 == name) {
+            Some(p) => p.1,
+            None => return None,
+        };
+
+        let mut keymap = KeyMap { bindings: Vec::new() };
+        for (k, key) in layout.iter().enumerate() {
+            keymap.bindings.push((key.to_string(), Binding::Pad(k as u8)));
+        }
+        if name == "azerty" {
+            for &(k, key) in &AZERTY_NUMBERS {
+                keymap.bindings.push((key.to_string(), Binding::Pad(k)));
+            }
+        }
+        for &(k, input) in &GAMEPAD {
+            keymap.bindings.push((input.to_string(), Binding::Pad(k)));
+        }
+        for &(_, hotkey, key) in &HOTKEYS {
+            keymap.bindings.push((key.to_string(), Binding::Hotkey(hotkey)));
+        }
+
+        Some(keymap)
+    }
+
+    /// The qwerty preset.
+    pub fn qwerty() -> KeyMap {
+        KeyMap::preset("qwerty").unwrap()
+    }
+
+    /// The names of the built-in layouts.
+    pub fn preset_names() -> Vec<&'static str> {
+        PRESETS.iter().map(|p| p.0).collect()
+    }
+
+    /// Bind names to binding, replacing whatever was bound to either.
+    /// Keyboard and gamepad bindings are replaced separately, so that
+    /// binding only keys keeps the gamepad bindings and the other way
+    /// around. Binding no names at all unbinds both.
+    pub fn bind(&mut self, binding: Binding, names: &[String]) {
+        let keyboard = names.iter().any(|n| !is_gamepad(n));
+        let gamepad = names.iter().any(|n| is_gamepad(n));
+
+        self.bindings.retain(|b| {
+            let replaced = b.1 == binding &&
+                (names.is_empty() || if is_gamepad(&b.0) { gamepad } else { keyboard });
+            !replaced && !names.iter().any(|n| n.eq_ignore_ascii_case(&b.0))
+        });
+        for name in names {
+            self.bindings.push((name.clone(), binding));
+        }
+    }
+
+    /// Apply a key map file on top of this one. Each line binds one CHIP8
+    /// key (0 to F) or hotkey to a list of key names, e.g.
+    ///
+    /// ```text
+    /// preset = "azerty"
+    /// 5 = "W", "Up", "Gamepad b"
+    /// quit = "Escape", "F10", "Gamepad back"
+    /// record = ""
+    /// ```
+    ///
+    /// An empty list unbinds. A preset line starts over from that layout.
+    pub fn apply_file(&mut self, fname: &str) -> Result<(), String> {
+        let f = match File::open(fname) {
+            Ok(f) => f,
+            Err(_) => return Err(format!("Could not open key map {}", fname)),
+        };
+
+        for line in BufReader::new(f).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => return Err(format!("Could not read key map {}", fname)),
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, val) = match line.find('=') {
+                Some(i) => (line[..i].trim(), line[(i + 1)..].trim()),
+                None => return Err(format!("Expected key = value, found {:?}", line)),
+            };
+
+            if key == "preset" {
+                let name = val.trim_matches('"');
+                *self = match KeyMap::preset(name) {
+                    Some(k) => k,
+                    None => return Err(format!("Unknown key map preset {:?}", name)),
+                };
+                continue;
+            }
+
+            let binding = if let Some(h) = HOTKEYS.iter().find(|h| h.0 == key) {
+                Binding::Hotkey(h.1)
+            } else if key.len() == 1 && u8::from_str_radix(key, 16).is_ok() {
+                Binding::Pad(u8::from_str_radix(key, 16).unwrap())
+            } else {
+                let hotkeys: Vec<&str> = HOTKEYS.iter().map(|h| h.0).collect();
+                return Err(format!("Unknown key map entry {:?} (expected 0 to F or one of {})",
+                                   key, hotkeys.join(", ")));
+            };
+
+            let names: Vec<String> = parse_names(val)?.into_iter()
+                .filter(|n| !n.is_empty())
+                .collect();
+            self.bind(binding, &names);
+        }
+
+        Ok(())
+    }
+
+    /// A preset by name, or else a key map file applied to qwerty.
+    pub fn from_arg(arg: &str) -> Result<KeyMap, String> {
+        match KeyMap::preset(arg) {
+            Some(k) => Ok(k),
+            None => {
+                let mut keymap = KeyMap::qwerty();
+                keymap.apply_file(arg)?;
+                Ok(keymap)
+            },
+        }
+    }
+
+    /// What the key called name is bound to, if anything.
+    pub fn binding(&self, name: &str) -> Option<Binding> {
+        self.bindings.iter()
+            .find(|b| b.0.eq_ignore_ascii_case(name))
+            .map(|b| b.1)
+    }
+}
@@ -0,0 +1,81 @@
+//! Names for addresses, from symbol files.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufReader;
+
+/// Labels maps addresses to symbolic names, loaded from a symbol file.
+/// Each line of the file is a hex address followed by a name, e.g.
+///
+/// ```text
+/// 0x2A4 draw_player
+/// ```
+///
+/// Blank lines and lines starting with # are ignored.
+pub struct Labels {
+    names: HashMap<u16, String>,
+}
+
+impl Labels {
+    /// No labels.
+    pub fn new() -> Labels {
+        Labels {
+            names: HashMap::new(),
+        }
+    }
+
+    /// Read a symbol file.
+    pub fn load(fname: &str) -> Result<Labels, &'static str> {
+        let f = match File::open(fname) {
+            Ok(f) => f,
+            Err(_) => return Err("I/O Error opening"),
+        };
+
+        let mut labels = Labels::new();
+
+        for line in BufReader::new(f).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => return Err("I/O Error reading"),
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let addr = words.next()
+                .map(|a| a.trim_start_matches("0x").trim_start_matches("0X"))
+                .and_then(|a| u16::from_str_radix(a, 16).ok());
+
+            match (addr, words.next()) {
+                (Some(addr), Some(name)) => {
+                    labels.names.insert(addr, name.to_string());
+                },
+                _ => return Err("Malformed label line"),
+            }
+        }
+
+        Ok(labels)
+    }
+
+    /// The label for addr, if it has one.
+    pub fn get(&self, addr: u16) -> Option<&str> {
+        self.names.get(&addr).map(|s| s.as_str())
+    }
+
+    /// The label for addr, or a generated name if it has none.
+    pub fn name(&self, addr: u16) -> String {
+        match self.get(addr) {
+            Some(name) => name.to_string(),
+            None => format!("sub_{:03X}", addr),
+        }
+    }
+}
+
+impl Default for Labels {
+    fn default() -> Labels {
+        Labels::new()
+    }
+}
@@ -0,0 +1,92 @@
+//! A CHIP8 emulator, as a library.
+//!
+//! The machine is `CPUState`. It runs ROMs through a `Frontend`, which
+//! shows the screen and reads the keys: the SDL window in `graphics` (with
+//! the `sdl` feature, on by default), the terminal in `tui` (on Unix), or
+//! `Headless` for running without either.
+//!
+//! `run` takes over until the ROM exits or the window closes. A host with
+//! its own loop instead runs the machine a frame at a time, passing in the
+//! keys and reading back the screen and the buzzer:
+//!
+//! ```no_run
+//! use chip8::{CPUState, ExecResult, Headless, read_rom};
+//!
+//! let rom = read_rom("pong.ch8", 0x200).unwrap();
+//! let mut cpu = CPUState::new(Box::new(Headless::new()));
+//! cpu.load_rom(&rom, 0x200);
+//!
+//! for frame in 0..600 {
+//!     cpu.set_key(0x1, frame % 20 < 10);
+//!     if cpu.run_frame() != ExecResult::Success {
+//!         break;
+//!     }
+//!     let lit = cpu.framebuffer().iter().filter(|&&p| p).count();
+//!     println!("{} pixels lit, V0 = {}, sound {}", lit, cpu.v(0), cpu.sound_active());
+//! }
+//! ```
+//!
+//! Around the machine are the instruction decoder (`parsing`), the
+//! debugger, profiler and coverage tools, screen and movie recording, and
+//! the settings: quirk profiles, palettes, key maps, config files and the
+//! ROM database.
+
+#![warn(missing_docs)]
+
+#[macro_use]
+extern crate nom;
+extern crate rand;
+#[cfg(feature = "sdl")]
+extern crate sdl2;
+#[cfg(unix)]
+extern crate libc;
+
+// The machine
+pub mod cpu;
+pub mod parsing;
+pub mod screen;
+pub mod quirks;
+pub mod random;
+
+// Frontends
+pub mod frontend;
+#[cfg(feature = "sdl")]
+pub mod graphics;
+#[cfg(unix)]
+pub mod tui;
+pub mod headless;
+pub mod keymap;
+#[cfg(feature = "sdl")]
+mod osd;
+pub mod palette;
+pub mod filter;
+
+// Recording
+mod png;
+mod gif;
+mod capture;
+pub mod recorder;
+pub mod movie;
+
+// Tools
+pub mod debugger;
+#[cfg(feature = "sdl")]
+mod debugview;
+pub mod expr;
+pub mod labels;
+pub mod profiler;
+pub mod coverage;
+
+// Settings
+pub mod config;
+mod sha1;
+
+pub use cpu::{CPUState, ExecResult, Step, read_rom, DEFAULT_IPS};
+pub use parsing::Instruction;
+pub use frontend::{Frontend, Status};
+pub use headless::Headless;
+#[cfg(unix)]
+pub use tui::Tui;
+#[cfg(feature = "sdl")]
+pub use graphics::Graphics;
+pub use quirks::Quirks;
@@ -1,25 +1,248 @@
-#[macro_use]
-extern crate nom;
+extern crate chip8;
 extern crate rand;
-extern crate sdl2;
 
-mod cpu;
-mod parsing;
-mod graphics;
+mod cli;
 
-use cpu::CPUState;
-use parsing::Instruction;
-use std::thread;
-use graphics::Graphics;
-use std::sync::Arc;
+use chip8::cpu::{CPUState, read_rom, DEFAULT_IPS};
+use chip8::frontend::Frontend;
+#[cfg(feature = "sdl")]
+use chip8::graphics::Graphics;
+#[cfg(unix)]
+use chip8::tui::Tui;
+use chip8::headless::Headless;
+use chip8::debugger::Debugger;
+use chip8::labels::Labels;
+use chip8::movie::Movie;
+use chip8::recorder::Recorder;
+use chip8::config;
+use chip8::config::{Config, RomInfo, Settings};
+use chip8::palette::Palette;
+use chip8::keymap::KeyMap;
+use cli::{Options, USAGE};
 use std::env;
+use std::process;
+use std::io;
+use std::fs::File;
+use std::path::Path;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let mut c = CPUState::new();
-    c.load_rom(&args[1]).unwrap();
+    let o = match Options::parse(env::args().skip(1)) {
+        Ok(Some(o)) => o,
+        Ok(None) => {
+            print!("{}", USAGE);
+            return;
+        },
+        Err(e) => {
+            eprintln!("chip8: {}\nRun chip8 --help for the options.", e);
+            process::exit(2);
+        },
+    };
+    let rom = o.rom.clone();
 
-    c.run();
+    // Read the ROM first, so that a bad one fails before a window opens
+    let program = read_rom(&rom, o.load_address).unwrap_or_else(|e| fail(&e));
 
-    return;
+    // Settings from the config file for every ROM, then from the ROM
+    // database and the config file for this ROM, then the command line
+    let config = if o.no_config {
+        Config::default()
+    } else if let Some(ref fname) = o.config {
+        Config::load(fname).unwrap_or_else(|e| fail(&e))
+    } else {
+        match Config::default_path() {
+            Some(ref path) if path.exists() => {
+                Config::load(&path.to_string_lossy()).unwrap_or_else(|e| fail(&e))
+            },
+            _ => Config::default(),
+        }
+    };
+    let hash = config::rom_hash(&program);
+    let mut settings = config.global().clone();
+    let mut info = None;
+    for &(s, i) in Config::database().rom(&hash).iter().chain(config.rom(&hash).iter()) {
+        settings.merge(s);
+        info.get_or_insert_with(RomInfo::default).merge(i);
+    }
+    settings.merge(&o.settings);
+
+    if o.info {
+        print_info(&rom, &hash, info.as_ref(), &settings);
+        return;
+    }
+    if let Some(ref info) = info {
+        println!("{}", info.describe());
+        if let Some(ref keys) = info.keys {
+            println!("Keys: {}", keys);
+        }
+    }
+
+    // Key bindings for just this ROM live next to it, e.g. pong.ch8.keys
+    let mut keymap = settings.keymap.unwrap_or_else(KeyMap::qwerty);
+    let keys = format!("{}.keys", rom);
+    if Path::new(&keys).exists() {
+        keymap.apply_file(&keys).unwrap_or_else(|e| fail(&e));
+    }
+
+    let movie = o.play_movie.as_ref().map(|fname| Movie::load(fname).unwrap_or_else(|e| fail(&e)));
+
+    let palette = settings.palette.unwrap_or_else(Palette::classic);
+    // Recordings are at the size the window starts out at
+    let recorder = o.record.as_ref().map(|fname| {
+        Recorder::new(fname, o.scale, palette, o.record_raw).unwrap_or_else(|e| fail(&e))
+    });
+
+    let frontend: Box<dyn Frontend> = if o.headless {
+        Box::new(Headless::new())
+    } else if o.terminal {
+        terminal(palette, keymap)
+    } else {
+        window(&o, palette, keymap)
+    };
+
+    let mut c = CPUState::new(frontend);
+    c.load_rom(&program, o.load_address);
+    c.set_ips(settings.ips.unwrap_or(DEFAULT_IPS));
+    c.set_unthrottled(o.unthrottled || o.headless);
+    c.set_frame_limit(o.frames);
+    c.set_random(o.random, o.seed.unwrap_or_else(rand::random));
+    c.set_quirks(settings.quirks.unwrap_or_default());
+    if let Some(recorder) = recorder {
+        c.start_recording(recorder);
+    }
+
+    if let Some(movie) = movie {
+        c.play_movie(movie);
+    } else if o.record_movie.is_some() {
+        c.record_movie();
+    }
+    if let Some(ref fname) = o.trace {
+        c.enable_trace(fname).unwrap_or_else(|e| {
+            fail(&format!("Could not create trace file {}: {}", fname, e))
+        });
+    }
+
+    // Symbols for the ROM live next to it, e.g. pong.ch8.sym
+    let sym = format!("{}.sym", rom);
+    let labels = if Path::new(&sym).exists() {
+        Labels::load(&sym).unwrap_or_else(|e| fail(&format!("{} in {}", e, sym)))
+    } else {
+        Labels::new()
+    };
+
+    if o.profile {
+        c.enable_profiler();
+    }
+    if o.coverage {
+        c.enable_coverage();
+    }
+
+    if o.debug {
+        Debugger::new().console(&mut c);
+    } else if o.gui_debug {
+        gui_debug(&mut c);
+    } else {
+        c.run();
+    }
+    c.frontend().suspend();
+    c.stop_recording();
+
+    if let Some(fname) = o.record_movie {
+        let movie = c.take_movie().unwrap();
+        movie.save(&fname).unwrap_or_else(|e| fail(&format!("Could not save movie {}: {}", fname, e)));
+        println!("Saved {} frames to {}", movie.frame_count(), fname);
+    }
+
+    if let Some(profiler) = c.profiler() {
+        profiler.write_report(&mut io::stdout(), c.memory(), &labels).unwrap_or_else(|e| {
+            fail(&format!("Could not write the profile: {}", e))
+        });
+    }
+
+    // Coverage goes to pong.ch8.cov (annotated listing) and pong.ch8.info (lcov)
+    if let Some(coverage) = c.coverage() {
+        let listing_name = format!("{}.cov", rom);
+        let lcov_name = format!("{}.info", rom);
+        let create = |fname: &str| File::create(fname).unwrap_or_else(|e| {
+            fail(&format!("Could not create {}: {}", fname, e))
+        });
+        let mut listing = create(&listing_name);
+        let mut lcov = create(&lcov_name);
+        let start = c.load_address() as usize;
+        let end = start + c.rom().len();
+
+        coverage.write_reports(&mut listing, &mut lcov, &listing_name,
+                               c.memory(), start..end, &labels).unwrap_or_else(|e| {
+            fail(&format!("Could not write {} and {}: {}", listing_name, lcov_name, e))
+        });
+        coverage.write_summary(&mut io::stdout(), c.memory(), start..end).unwrap_or_else(|e| {
+            fail(&format!("Could not write the coverage summary: {}", e))
+        });
+    }
+
+}
+
+// The SDL window, set up from the options.
+#[cfg(feature = "sdl")]
+fn window(o: &Options, palette: Palette, keymap: KeyMap) -> Box<dyn Frontend> {
+    let mut graphics = Graphics::new(o.scale, o.scale_mode, o.fullscreen, o.vsync);
+    graphics.set_palette(palette);
+    graphics.set_filter(o.filter.clone());
+    graphics.set_keymap(keymap);
+    if let Some(stem) = Path::new(&o.rom).file_stem() {
+        graphics.set_capture_prefix(&stem.to_string_lossy());
+    }
+    Box::new(graphics)
+}
+
+// Options::parse rejects the window when built without SDL.
+#[cfg(not(feature = "sdl"))]
+fn window(_o: &Options, _palette: Palette, _keymap: KeyMap) -> Box<dyn Frontend> {
+    unreachable!()
+}
+
+#[cfg(unix)]
+fn terminal(palette: Palette, keymap: KeyMap) -> Box<dyn Frontend> {
+    Box::new(Tui::new(palette, keymap))
+}
+
+// Options::parse rejects --terminal where there is no terminal frontend.
+#[cfg(not(unix))]
+fn terminal(_palette: Palette, _keymap: KeyMap) -> Box<dyn Frontend> {
+    unreachable!()
+}
+
+#[cfg(feature = "sdl")]
+fn gui_debug(c: &mut CPUState) {
+    Debugger::new().gui(c);
+}
+
+#[cfg(not(feature = "sdl"))]
+fn gui_debug(_c: &mut CPUState) {
+    unreachable!()
+}
+
+// Print what is known about a ROM and the settings it would run with.
+fn print_info(rom: &str, hash: &str, info: Option<&RomInfo>, settings: &Settings) {
+    println!("{}", rom);
+    println!("  SHA-1:    {}", hash);
+    match info {
+        Some(info) => {
+            println!("  ROM:      {}", info.describe());
+            if let Some(ref keys) = info.keys {
+                println!("  Keys:     {}", keys);
+            }
+        },
+        None => println!("  ROM:      Not in the database or config"),
+    }
+    if let Some(ref platform) = settings.platform {
+        println!("  Platform: {}", platform);
+    }
+    println!("  Quirks:   {}", settings.quirks.unwrap_or_default().name);
+    println!("  IPS:      {}", settings.ips.unwrap_or(DEFAULT_IPS));
+}
+
+// Report an error that stops the emulator from starting or finishing.
+fn fail(message: &str) -> ! {
+    eprintln!("chip8: {}", message);
+    process::exit(1);
 }
@@ -0,0 +1,321 @@
+//! Recordings of the keys, for replaying a run exactly.
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+
+use random::RandomKind;
+use quirks::Quirks;
+
+/// A Movie is a recording of the CHIP8 keys, taken once per frame, along
+/// with everything else that decides how a run goes: the random number
+/// generator and its seed, the instructions per second and the quirks.
+/// Playing it back on the same ROM repeats the run exactly.
+///
+/// Movie files are text. A few settings come first, then one line per run
+/// of identical frames: the pressed keys as a hex mask (bit n is key n) and
+/// how many frames they were held for, e.g.
+///
+/// ```text
+/// random = xorshift
+/// seed = 0x5EED
+/// ips = 600
+/// quirks = vip
+/// 0000 120
+/// 0020 3
+/// ```
+///
+/// The seed is in decimal, or in hex with 0x in front. Blank lines and
+/// lines starting with # are ignored.
+pub struct Movie {
+    /// The random number generator RND used
+    pub random: RandomKind,
+    /// What the generator was seeded with
+    pub seed: u64,
+    /// Instructions per second
+    pub ips: u32,
+    /// The quirks the run had
+    pub quirks: Quirks,
+
+    frames: Vec<(u16, u32)>, // (keys, frame count) runs
+    pos: usize,              // Run being played back
+    pos_frames: u32,         // Frames played back from that run
+    playing: bool,
+}
+
+fn to_mask(keys: &[bool; 16]) -> u16 {
+    let mut mask = 0;
+    for (k, &down) in keys.iter().enumerate() {
+        if down {
+            mask |= 1 << k;
+        }
+    }
+    mask
+}
+
+fn from_mask(mask: u16) -> [bool; 16] {
+    let mut keys = [false; 16];
+    for (k, down) in keys.iter_mut().enumerate() {
+        *down = mask & (1 << k) != 0;
+    }
+    keys
+}
+
+// A seed in decimal, or in hex with 0x in front.
+fn parse_seed(s: &str) -> Option<u64> {
+    if s.starts_with("0x") || s.starts_with("0X") {
+        u64::from_str_radix(&s[2..], 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+impl Movie {
+    /// An empty movie, to record into.
+    pub fn new(random: RandomKind, seed: u64, ips: u32, quirks: Quirks) -> Movie {
+        Movie {
+            random: random,
+            seed: seed,
+            ips: ips,
+            quirks: quirks,
+
+            frames: Vec::new(),
+            pos: 0,
+            pos_frames: 0,
+            playing: false,
+        }
+    }
+
+    /// Load a movie to play back.
+    pub fn load(fname: &str) -> Result<Movie, String> {
+        let f = match File::open(fname) {
+            Ok(f) => f,
+            Err(_) => return Err(format!("Could not open movie {}", fname)),
+        };
+
+        let mut movie = Movie::new(RandomKind::Xorshift, 0, 0, Quirks::default());
+        movie.playing = true;
+
+        for line in BufReader::new(f).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => return Err(format!("Could not read movie {}", fname)),
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(i) = line.find('=') {
+                let (key, val) = (line[..i].trim(), line[(i + 1)..].trim());
+                let parsed = match key {
+                    "random" => RandomKind::parse(val).map(|r| movie.random = r).is_some(),
+                    "seed" => parse_seed(val).map(|s| movie.seed = s).is_some(),
+                    "ips" => val.parse().map(|i| movie.ips = i).is_ok(),
+                    "quirks" => Quirks::profile(val).map(|q| movie.quirks = q).is_some(),
+                    _ => return Err(format!("Unknown movie setting {:?}", key)),
+                };
+                if !parsed {
+                    return Err(format!("Malformed movie setting {:?}", line));
+                }
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let mask = words.next().and_then(|m| u16::from_str_radix(m, 16).ok());
+            let count = words.next().and_then(|c| c.parse().ok());
+            match (mask, count) {
+                (Some(mask), Some(count)) => movie.frames.push((mask, count)),
+                _ => return Err(format!("Malformed movie frame {:?}", line)),
+            }
+        }
+
+        if movie.ips == 0 {
+            return Err(format!("Movie {} doesn't give its instructions per second", fname));
+        }
+
+        Ok(movie)
+    }
+
+    /// Write the movie to fname.
+    pub fn save(&self, fname: &str) -> io::Result<()> {
+        let mut out = File::create(fname)?;
+
+        writeln!(out, "# CHIP8 movie")?;
+        writeln!(out, "random = {}", self.random.name())?;
+        writeln!(out, "seed = {:#X}", self.seed)?;
+        writeln!(out, "ips = {}", self.ips)?;
+        writeln!(out, "quirks = {}", self.quirks.name)?;
+        for &(mask, count) in &self.frames {
+            writeln!(out, "{:04X} {}", mask, count)?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether this is a movie being played back rather than recorded.
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Append a frame with keys held.
+    pub fn record(&mut self, keys: &[bool; 16]) {
+        let mask = to_mask(keys);
+        match self.frames.last_mut() {
+            Some(last) if last.0 == mask => last.1 += 1,
+            _ => self.frames.push((mask, 1)),
+        }
+    }
+
+    /// The keys held in the next frame, or None once the movie is over.
+    pub fn next_frame(&mut self) -> Option<[bool; 16]> {
+        while self.pos < self.frames.len() && self.pos_frames >= self.frames[self.pos].1 {
+            self.pos += 1;
+            self.pos_frames = 0;
+        }
+        if self.pos == self.frames.len() {
+            return None;
+        }
+
+        self.pos_frames += 1;
+        Some(from_mask(self.frames[self.pos].0))
+    }
+
+    /// Frames recorded, or in the movie being played back.
+    pub fn frame_count(&self) -> u64 {
+        self.frames.iter().map(|f| f.1 as u64).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    // A file under the temporary directory, removed when dropped.
+    struct TempFile(String);
+
+    impl TempFile {
+        fn new(name: &str) -> TempFile {
+            let path = env::temp_dir().join(format!("chip8-{}-{}", std::process::id(), name));
+            TempFile(path.to_string_lossy().into_owned())
+        }
+
+        fn with_text(name: &str, text: &str) -> TempFile {
+            let f = TempFile::new(name);
+            fs::write(&f.0, text).unwrap();
+            f
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn keys(down: &[usize]) -> [bool; 16] {
+        let mut keys = [false; 16];
+        for &k in down {
+            keys[k] = true;
+        }
+        keys
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut movie = Movie::new(RandomKind::Vip, 0x5EED, 700, Quirks::profile("schip").unwrap());
+        movie.record(&keys(&[]));
+        movie.record(&keys(&[]));
+        movie.record(&keys(&[5]));
+        movie.record(&keys(&[0, 0xF]));
+        movie.record(&keys(&[]));
+        assert_eq!(movie.frame_count(), 5);
+        assert!(!movie.is_playing());
+
+        let f = TempFile::new("round-trip.movie");
+        movie.save(&f.0).unwrap();
+
+        // Runs of identical frames take one line each
+        let text = fs::read_to_string(&f.0).unwrap();
+        assert!(text.contains("\n0000 2\n0020 1\n8001 1\n0000 1\n"), "{}", text);
+
+        let mut loaded = Movie::load(&f.0).unwrap();
+        assert!(loaded.is_playing());
+        assert_eq!(loaded.random, RandomKind::Vip);
+        assert_eq!(loaded.seed, 0x5EED);
+        assert_eq!(loaded.ips, 700);
+        assert_eq!(loaded.quirks, Quirks::profile("schip").unwrap());
+        assert_eq!(loaded.frame_count(), 5);
+
+        assert_eq!(loaded.next_frame(), Some(keys(&[])));
+        assert_eq!(loaded.next_frame(), Some(keys(&[])));
+        assert_eq!(loaded.next_frame(), Some(keys(&[5])));
+        assert_eq!(loaded.next_frame(), Some(keys(&[0, 0xF])));
+        assert_eq!(loaded.next_frame(), Some(keys(&[])));
+        assert_eq!(loaded.next_frame(), None);
+        assert_eq!(loaded.next_frame(), None);
+    }
+
+    #[test]
+    fn load() {
+        let f = TempFile::with_text("load.movie", "# A comment\n\
+                                                   \n\
+                                                   ips = 600\n\
+                                                   0001 0\n\
+                                                   0002 2\n");
+        let mut movie = Movie::load(&f.0).unwrap();
+
+        // Settings not given keep their defaults
+        assert_eq!(movie.random, RandomKind::Xorshift);
+        assert_eq!(movie.seed, 0);
+        assert_eq!(movie.quirks, Quirks::default());
+
+        // Empty runs are skipped
+        assert_eq!(movie.next_frame(), Some(keys(&[1])));
+        assert_eq!(movie.next_frame(), Some(keys(&[1])));
+        assert_eq!(movie.next_frame(), None);
+    }
+
+    #[test]
+    fn seeds() {
+        let seed = |text: &str| {
+            let f = TempFile::with_text("seed.movie", &format!("ips = 600\n{}\n", text));
+            Movie::load(&f.0).map(|m| m.seed)
+        };
+
+        assert_eq!(seed("seed = 1234"), Ok(1234));
+        assert_eq!(seed("seed = 0x1234"), Ok(0x1234));
+        assert_eq!(seed("seed = 0X5eed"), Ok(0x5EED));
+        assert!(seed("seed = 5EED").is_err());
+    }
+
+    #[test]
+    fn errors() {
+        let error = |name: &str, text: &str| {
+            let f = TempFile::with_text(name, text);
+            Movie::load(&f.0).err().unwrap()
+        };
+
+        assert_eq!(error("e1.movie", "ips = 600\nspeed = 2\n"),
+                   "Unknown movie setting \"speed\"");
+        assert_eq!(error("e2.movie", "ips = fast\n"),
+                   "Malformed movie setting \"ips = fast\"");
+        assert_eq!(error("e3.movie", "ips = 600\nrandom = dice\n"),
+                   "Malformed movie setting \"random = dice\"");
+        assert_eq!(error("e4.movie", "ips = 600\nquirks = nonsense\n"),
+                   "Malformed movie setting \"quirks = nonsense\"");
+        assert_eq!(error("e5.movie", "ips = 600\n0001\n"),
+                   "Malformed movie frame \"0001\"");
+        assert_eq!(error("e6.movie", "ips = 600\nXYZ 1\n"),
+                   "Malformed movie frame \"XYZ 1\"");
+
+        let f = TempFile::with_text("e7.movie", "0001 1\n");
+        assert_eq!(Movie::load(&f.0).err().unwrap(),
+                   format!("Movie {} doesn't give its instructions per second", f.0));
+
+        assert!(Movie::load("/nonexistent/chip8.movie").is_err());
+    }
+}
@@ -0,0 +1,184 @@
+use std::time::{Duration, Instant};
+
+use frontend::Status;
+
+// The on-screen display: a few lines of statistics about the running
+// machine, plus short messages such as "Saved screenshot". Frontends draw
+// its lines with the built-in font below.
+
+// How long a message stays up.
+const MESSAGE_SECS: u64 = 3;
+
+// Statistics are averaged over this long.
+const STATS_MILLIS: u64 = 1000;
+
+pub struct Osd {
+    visible: bool,                    // Show the statistics; messages show regardless
+    messages: Vec<(String, Instant)>, // Text and when it was posted
+
+    status: Status,
+    window_start: Instant, // Start of the current averaging window
+    window_status: Status, // Status as of window_start
+    window_frames: u32,    // Frames presented since window_start
+
+    fps: f32,
+    ips: f32,
+    speed: f32, // Emulated 60 Hz frames per real 60th of a second
+}
+
+impl Osd {
+    pub fn new() -> Osd {
+        Osd {
+            visible: false,
+            messages: Vec::new(),
+
+            status: Status::default(),
+            window_start: Instant::now(),
+            window_status: Status::default(),
+            window_frames: 0,
+
+            fps: 0.0,
+            ips: 0.0,
+            speed: 0.0,
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    // Show text for a few seconds.
+    pub fn message(&mut self, text: &str) {
+        self.messages.push((text.to_string(), Instant::now()));
+    }
+
+    pub fn update(&mut self, status: &Status) {
+        self.status = status.clone();
+    }
+
+    // Count a presented frame, and update the averages once a window of
+    // time has passed.
+    pub fn frame_presented(&mut self) {
+        self.window_frames += 1;
+
+        let elapsed = self.window_start.elapsed();
+        if elapsed < Duration::from_millis(STATS_MILLIS) {
+            return;
+        }
+
+        let secs = elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 / 1e9;
+        let instructions = self.status.instructions - self.window_status.instructions;
+        let frames = self.status.frames - self.window_status.frames;
+
+        self.fps = self.window_frames as f32 / secs;
+        self.ips = instructions as f32 / secs;
+        self.speed = frames as f32 / secs / 60.0;
+
+        self.window_start = Instant::now();
+        self.window_status = self.status.clone();
+        self.window_frames = 0;
+    }
+
+    // Whether there is anything to draw; while there is, the display is
+    // refreshed every frame to keep it current.
+    pub fn active(&mut self) -> bool {
+        let expiry = Duration::from_secs(MESSAGE_SECS);
+        self.messages.retain(|m| m.1.elapsed() < expiry);
+
+        self.visible || !self.messages.is_empty()
+    }
+
+    // Lines for the top left corner.
+    pub fn stats(&self) -> Vec<String> {
+        if !self.visible {
+            return Vec::new();
+        }
+
+        let target = if self.status.speed > 0.0 {
+            format!("{}X", self.status.speed)
+        } else {
+            String::from("MAX")
+        };
+
+        let mut lines = vec![
+            format!("FPS {:.0}  IPS {:.0}/{}", self.fps, self.ips, self.status.ips),
+            format!("SPEED {:.0}% ({})", self.speed * 100.0, target),
+            format!("QUIRKS {}", self.status.quirks),
+        ];
+        if self.status.paused {
+            lines.push(String::from("PAUSED"));
+        }
+
+        lines
+    }
+
+    // Lines for the bottom left corner, oldest first.
+    pub fn messages(&self) -> Vec<String> {
+        self.messages.iter().map(|m| m.0.clone()).collect()
+    }
+}
+
+impl Default for Osd {
+    fn default() -> Osd {
+        Osd::new()
+    }
+}
+
+// A 3x5 pixel font. Each glyph is five rows, most significant bit leftmost.
+// Lowercase letters are drawn as uppercase; unknown characters as blanks.
+pub const GLYPH_WIDTH: u32 = 3;
+pub const GLYPH_HEIGHT: u32 = 5;
+
+pub fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '(' => [0b010, 0b100, 0b100, 0b100, 0b010],
+        ')' => [0b010, 0b001, 0b001, 0b001, 0b010],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        _   => [0; 5],
+    }
+}
@@ -0,0 +1,156 @@
+//! The colors the screen is shown in.
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufReader;
+
+/// A Palette holds the display colors as [r, g, b].
+/// Index 0 is the background and 1 the foreground. Renderers with a second
+/// bit plane (XO-CHIP) use 2 for pixels set only in plane 2 and 3 for
+/// pixels set in both planes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    /// Background, foreground, plane 2 and both planes
+    pub colors: [[u8; 3]; 4],
+}
+
+// Built-in themes, by name.
+static THEMES: [(&str, Palette); 4] = [
+    ("classic", Palette { colors: [[0x00, 0x00, 0x00], [0xFF, 0xFF, 0xFF],
+                                   [0xAA, 0xAA, 0xAA], [0x55, 0x55, 0x55]] }),
+    ("amber",   Palette { colors: [[0x1A, 0x0E, 0x00], [0xFF, 0xB0, 0x00],
+                                   [0xCC, 0x6A, 0x00], [0xFF, 0xE0, 0x80]] }),
+    ("green",   Palette { colors: [[0x00, 0x14, 0x00], [0x33, 0xFF, 0x33],
+                                   [0x1A, 0x99, 0x1A], [0xAA, 0xFF, 0xAA]] }),
+    ("lcd",     Palette { colors: [[0x9B, 0xBC, 0x0F], [0x0F, 0x38, 0x0F],
+                                   [0x30, 0x62, 0x30], [0x8B, 0xAC, 0x0F]] }),
+];
+
+// Parse a color written as #RRGGBB, optionally in double quotes.
+fn parse_color(s: &str) -> Option<[u8; 3]> {
+    let s = s.trim().trim_matches('"');
+    if !s.starts_with('#') || s.len() != 7 || !s.is_ascii() {
+        return None;
+    }
+
+    let mut color = [0; 3];
+    for i in 0..3 {
+        color[i] = match u8::from_str_radix(&s[(1 + 2 * i)..(3 + 2 * i)], 16) {
+            Ok(c) => c,
+            Err(_) => return None,
+        };
+    }
+
+    Some(color)
+}
+
+impl Palette {
+    /// White on black.
+    pub fn classic() -> Palette {
+        THEMES[0].1
+    }
+
+    /// A built-in theme by name.
+    pub fn theme(name: &str) -> Option<Palette> {
+        THEMES.iter().find(|t| t.0 == name).map(|t| t.1)
+    }
+
+    /// The names of the built-in themes.
+    pub fn theme_names() -> Vec<&'static str> {
+        THEMES.iter().map(|t| t.0).collect()
+    }
+
+    /// Load a palette file. Each line sets one color, e.g.
+    ///
+    /// ```text
+    /// background = "#000000"
+    /// foreground = "#FFFFFF"
+    /// plane2 = "#AAAAAA"
+    /// both = "#555555"
+    /// ```
+    ///
+    /// Colors not given keep their classic values. A line `theme = "amber"`
+    /// starts from a built-in theme instead.
+    pub fn load(fname: &str) -> Result<Palette, String> {
+        let f = match File::open(fname) {
+            Ok(f) => f,
+            Err(_) => return Err(format!("Could not open palette {}", fname)),
+        };
+
+        let mut palette = Palette::classic();
+
+        for line in BufReader::new(f).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => return Err(format!("Could not read palette {}", fname)),
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, val) = match line.find('=') {
+                Some(i) => (line[..i].trim(), line[(i + 1)..].trim()),
+                None => return Err(format!("Expected key = value, found {:?}", line)),
+            };
+
+            if key == "theme" {
+                let name = val.trim_matches('"');
+                palette = match Palette::theme(name) {
+                    Some(p) => p,
+                    None => return Err(format!("Unknown theme {:?}", name)),
+                };
+                continue;
+            }
+
+            let index = match key {
+                "background" => 0,
+                "foreground" => 1,
+                "plane2"     => 2,
+                "both"       => 3,
+                _ => return Err(format!("Unknown palette key {:?}", key)),
+            };
+            palette.colors[index] = match parse_color(val) {
+                Some(c) => c,
+                None => return Err(format!("Expected a color like \"#RRGGBB\", found {}", val)),
+            };
+        }
+
+        Ok(palette)
+    }
+
+    /// A built-in theme by name, or else a palette file by path.
+    pub fn from_arg(arg: &str) -> Result<Palette, String> {
+        match Palette::theme(arg) {
+            Some(p) => Ok(p),
+            None => Palette::load(arg),
+        }
+    }
+
+    /// The color of unlit pixels.
+    pub fn background(&self) -> [u8; 3] {
+        self.colors[0]
+    }
+
+    /// The color of lit pixels.
+    pub fn foreground(&self) -> [u8; 3] {
+        self.colors[1]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors() {
+        assert_eq!(parse_color("#FFB000"), Some([0xFF, 0xB0, 0x00]));
+        assert_eq!(parse_color(" \"#1a0e00\" "), Some([0x1A, 0x0E, 0x00]));
+        assert_eq!(parse_color("FFB000"), None);
+        assert_eq!(parse_color("#FFB00"), None);
+        assert_eq!(parse_color("#FFB0GG"), None);
+
+        // Not split in the middle of a character
+        assert_eq!(parse_color("#a\u{e9}bbbb"), None);
+    }
+}
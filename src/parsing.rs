@@ -1,42 +1,82 @@
+//! Decoding and disassembling CHIP8 instructions.
+
 use nom::{IResult, ErrorKind};
+use std::fmt;
 
+/// A decoded CHIP8 instruction. Register operands are register numbers,
+/// 0 to F.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Instruction {
+    /// Call a machine code routine, ignored
     Sys(u16),
+    /// Clear the screen
     Cls,
+    /// Return from a subroutine
     Ret,
+    /// Jump
     Jp(u16),
+    /// Call a subroutine
     Call(u16),
-    SeV(u8, u8), // Skip equal value
-    SneV(u8, u8),// Skip non-equal value
-    Se(u8, u8),  // Skip equal register
-    LdV(u8, u8), // Load value
-    AddV(u8, u8),// Add value
-    Ld(u8, u8),  // Load register
+    /// Skip equal value
+    SeV(u8, u8),
+    /// Skip non-equal value
+    SneV(u8, u8),
+    /// Skip equal register
+    Se(u8, u8),
+    /// Load value
+    LdV(u8, u8),
+    /// Add value
+    AddV(u8, u8),
+    /// Load register
+    Ld(u8, u8),
+    /// Vx |= Vy
     Or(u8, u8),
+    /// Vx &= Vy
     And(u8, u8),
+    /// Vx ^= Vy
     Xor(u8, u8),
-    Add(u8, u8), // Add registers
+    /// Add registers
+    Add(u8, u8),
+    /// Vx -= Vy, VF set if Vx was greater
     Sub(u8, u8),
-    Shr(u8),
+    /// Shift right; VY is the source with the shift_vy quirk
+    Shr(u8, u8),
+    /// Vx = Vy - Vx, VF set if Vy was greater
     Subn(u8, u8),
-    Shl(u8),
+    /// Shift left; VY is the source with the shift_vy quirk
+    Shl(u8, u8),
+    /// Skip non-equal register
     Sne(u8, u8),
-    LdI(u16),    // Load I register
+    /// Load I register
+    LdI(u16),
+    /// Jump to the address plus V0, or VX with the jump_vx quirk
     JpV0(u16),
+    /// Vx = a random byte ANDed with a value
     Rnd(u8, u8),
+    /// Draw an n byte sprite from `[I]` at (Vx, Vy)
     Drw(u8, u8, u8),
+    /// Skip if key Vx is pressed
     Skp(u8),
+    /// Skip if key Vx is not pressed
     Sknp(u8),
-    LdDt(u8),     // Load with delay timer
-    LdK(u8),      // Load key press
-    LdTd(u8),     // Set delay timer to Vx
-    LdSt(u8),     // Load sound timer
-    AddI(u8),     // Add Vx to I register
-    LdS(u8),      // Set I to sprite location for digit Vx
-    LdBCD(u8),    // Store BCD representation of Vx
-    LdVM(u8),     // Store reg V0-Vx in [I]
-    LdMV(u8),     // Store [I] in V0-Vx
+    /// Load with delay timer
+    LdDt(u8),
+    /// Load key press
+    LdK(u8),
+    /// Set delay timer to Vx
+    LdTd(u8),
+    /// Load sound timer
+    LdSt(u8),
+    /// Add Vx to I register
+    AddI(u8),
+    /// Set I to sprite location for digit Vx
+    LdS(u8),
+    /// Store BCD representation of Vx
+    LdBCD(u8),
+    /// Store reg V0-Vx in `[I]`
+    LdVM(u8),
+    /// Store `[I]` in V0-Vx
+    LdMV(u8),
 }
 
 fn parse_noarg(inp: (&[u8], usize)) -> IResult<(&[u8], usize), Instruction> {
@@ -181,9 +221,9 @@ fn parse_twoarg_xy(inp: (&[u8], usize)) -> IResult<(&[u8], usize), Instruction>
         (0x8, 0x3) => Instruction::Xor(x, y),
         (0x8, 0x4) => Instruction::Add(x, y),
         (0x8, 0x5) => Instruction::Sub(x, y),
-        (0x8, 0x6) => Instruction::Shr(x),
+        (0x8, 0x6) => Instruction::Shr(x, y),
         (0x8, 0x7) => Instruction::Subn(x, y),
-        (0x8, 0xE) => Instruction::Shl(x),
+        (0x8, 0xE) => Instruction::Shl(x, y),
         (0x9, 0x0) => Instruction::Sne(x, y),
         _          => return IResult::Error(ErrorKind::TagBits),
     };
@@ -243,6 +283,7 @@ named!(parse_instructions<&[u8], Vec<Instruction>>, do_parse!(
 ));
 
 impl Instruction {
+    /// Decode the instruction at the start of s, if it decodes.
     pub fn from_slice_one(s: &[u8]) -> Option<Instruction> {
         let parsed = parse_instruction(s);
 
@@ -253,6 +294,51 @@ impl Instruction {
         }
     }
 
+    /// The name of this instruction's variant, e.g. "LdV" for LdV(1, 2).
+    pub fn name(&self) -> &'static str {
+        use self::Instruction::*;
+
+        match *self {
+            Sys(..)         => "Sys",
+            Cls             => "Cls",
+            Ret             => "Ret",
+            Jp(..)          => "Jp",
+            Call(..)        => "Call",
+            SeV(..)         => "SeV",
+            SneV(..)        => "SneV",
+            Se(..)          => "Se",
+            LdV(..)         => "LdV",
+            AddV(..)        => "AddV",
+            Ld(..)          => "Ld",
+            Or(..)          => "Or",
+            And(..)         => "And",
+            Xor(..)         => "Xor",
+            Add(..)         => "Add",
+            Sub(..)         => "Sub",
+            Shr(..)         => "Shr",
+            Subn(..)        => "Subn",
+            Shl(..)         => "Shl",
+            Sne(..)         => "Sne",
+            LdI(..)         => "LdI",
+            JpV0(..)        => "JpV0",
+            Rnd(..)         => "Rnd",
+            Drw(..)         => "Drw",
+            Skp(..)         => "Skp",
+            Sknp(..)        => "Sknp",
+            LdDt(..)        => "LdDt",
+            LdK(..)         => "LdK",
+            LdTd(..)        => "LdTd",
+            LdSt(..)        => "LdSt",
+            AddI(..)        => "AddI",
+            LdS(..)         => "LdS",
+            LdBCD(..)       => "LdBCD",
+            LdVM(..)        => "LdVM",
+            LdMV(..)        => "LdMV",
+        }
+    }
+
+    /// Decode all of s. Panics unless s holds only whole, valid
+    /// instructions.
     pub fn from_slice(s: &[u8]) -> Vec<Instruction> {
         let parsed = parse_instructions(s);
 
@@ -261,3 +347,48 @@ impl Instruction {
         }
     }
 }
+
+// Disassemble an instruction into the usual CHIP8 assembly mnemonics.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Instruction::*;
+
+        match *self {
+            Sys(addr)       => write!(f, "SYS {:#05X}", addr),
+            Cls             => write!(f, "CLS"),
+            Ret             => write!(f, "RET"),
+            Jp(addr)        => write!(f, "JP {:#05X}", addr),
+            Call(addr)      => write!(f, "CALL {:#05X}", addr),
+            SeV(vx, byte)   => write!(f, "SE V{:X}, {:#04X}", vx, byte),
+            SneV(vx, byte)  => write!(f, "SNE V{:X}, {:#04X}", vx, byte),
+            Se(vx, vy)      => write!(f, "SE V{:X}, V{:X}", vx, vy),
+            LdV(vx, byte)   => write!(f, "LD V{:X}, {:#04X}", vx, byte),
+            AddV(vx, byte)  => write!(f, "ADD V{:X}, {:#04X}", vx, byte),
+            Ld(vx, vy)      => write!(f, "LD V{:X}, V{:X}", vx, vy),
+            Or(vx, vy)      => write!(f, "OR V{:X}, V{:X}", vx, vy),
+            And(vx, vy)     => write!(f, "AND V{:X}, V{:X}", vx, vy),
+            Xor(vx, vy)     => write!(f, "XOR V{:X}, V{:X}", vx, vy),
+            Add(vx, vy)     => write!(f, "ADD V{:X}, V{:X}", vx, vy),
+            Sub(vx, vy)     => write!(f, "SUB V{:X}, V{:X}", vx, vy),
+            Shr(vx, vy)     => write!(f, "SHR V{:X}, V{:X}", vx, vy),
+            Subn(vx, vy)    => write!(f, "SUBN V{:X}, V{:X}", vx, vy),
+            Shl(vx, vy)     => write!(f, "SHL V{:X}, V{:X}", vx, vy),
+            Sne(vx, vy)     => write!(f, "SNE V{:X}, V{:X}", vx, vy),
+            LdI(addr)       => write!(f, "LD I, {:#05X}", addr),
+            JpV0(addr)      => write!(f, "JP V0, {:#05X}", addr),
+            Rnd(vx, byte)   => write!(f, "RND V{:X}, {:#04X}", vx, byte),
+            Drw(vx, vy, n)  => write!(f, "DRW V{:X}, V{:X}, {}", vx, vy, n),
+            Skp(vx)         => write!(f, "SKP V{:X}", vx),
+            Sknp(vx)        => write!(f, "SKNP V{:X}", vx),
+            LdDt(vx)        => write!(f, "LD V{:X}, DT", vx),
+            LdK(vx)         => write!(f, "LD V{:X}, K", vx),
+            LdTd(vx)        => write!(f, "LD DT, V{:X}", vx),
+            LdSt(vx)        => write!(f, "LD ST, V{:X}", vx),
+            AddI(vx)        => write!(f, "ADD I, V{:X}", vx),
+            LdS(vx)         => write!(f, "LD F, V{:X}", vx),
+            LdBCD(vx)       => write!(f, "LD B, V{:X}", vx),
+            LdVM(vx)        => write!(f, "LD [I], V{:X}", vx),
+            LdMV(vx)        => write!(f, "LD V{:X}, [I]", vx),
+        }
+    }
+}
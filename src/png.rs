@@ -0,0 +1,222 @@
+use std::io;
+use std::io::prelude::*;
+
+// A small PNG encoder for framebuffer captures.
+// Images are 8-bit RGB, compressed with fixed-Huffman deflate. The matcher
+// only looks back one pixel, one byte and one row, which is all it takes
+// to squeeze scaled-up CHIP8 screens down to a few kilobytes.
+
+static PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+fn crc32(chunks: &[&[u8]]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+
+    for data in chunks {
+        for &byte in data.iter() {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+            }
+        }
+    }
+
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let mut a = 1u32;
+    let mut b = 0u32;
+
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+
+    (b << 16) | a
+}
+
+pub fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    out.write_all(&crc32(&[kind, data]).to_be_bytes())
+}
+
+// Writes bits least significant first, as deflate wants them.
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u32,
+    nbits: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, bits: u32, n: u32) {
+        self.acc |= bits << self.nbits;
+        self.nbits += n;
+
+        while self.nbits >= 8 {
+            self.bytes.push(self.acc as u8);
+            self.acc >>= 8;
+            self.nbits -= 8;
+        }
+    }
+
+    // Huffman codes are defined most significant bit first.
+    fn write_code(&mut self, code: u32, n: u32) {
+        let mut rev = 0;
+        for i in 0..n {
+            rev |= ((code >> i) & 1) << (n - 1 - i);
+        }
+
+        self.write(rev, n);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.nbits > 0 {
+            self.bytes.push(self.acc as u8);
+        }
+
+        self.bytes
+    }
+}
+
+// Base values and extra bits of the deflate length and distance codes.
+static LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+                                 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+static LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+                                 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+static DIST_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+                               257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+                               8193, 12289, 16385, 24577];
+static DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+                               7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+fn write_literal(w: &mut BitWriter, sym: u32) {
+    match sym {
+        0..=143   => w.write_code(0x30 + sym, 8),
+        144..=255 => w.write_code(0x190 + sym - 144, 9),
+        256..=279 => w.write_code(sym - 256, 7),
+        _         => w.write_code(0xC0 + sym - 280, 8),
+    }
+}
+
+fn write_match(w: &mut BitWriter, len: usize, dist: usize) {
+    let li = LENGTH_BASE.iter().rposition(|&b| b as usize <= len).unwrap();
+    write_literal(w, 257 + li as u32);
+    w.write((len - LENGTH_BASE[li] as usize) as u32, LENGTH_EXTRA[li] as u32);
+
+    let di = DIST_BASE.iter().rposition(|&b| b as usize <= dist).unwrap();
+    w.write_code(di as u32, 5);
+    w.write((dist - DIST_BASE[di] as usize) as u32, DIST_EXTRA[di] as u32);
+}
+
+// Compress data as a zlib stream, trying back references at the given
+// distances.
+fn zlib(data: &[u8], distances: &[usize]) -> Vec<u8> {
+    let mut w = BitWriter { bytes: vec![0x78, 0x01], acc: 0, nbits: 0 };
+
+    w.write(1, 1); // Final block
+    w.write(1, 2); // Fixed Huffman codes
+
+    let mut i = 0;
+    while i < data.len() {
+        let mut best = (0, 0);
+        for &dist in distances {
+            if dist == 0 || dist > i || dist > 32768 {
+                continue;
+            }
+
+            let mut len = 0;
+            while len < 258 && i + len < data.len() && data[i + len] == data[i + len - dist] {
+                len += 1;
+            }
+            if len > best.0 {
+                best = (len, dist);
+            }
+        }
+
+        if best.0 >= 3 {
+            write_match(&mut w, best.0, best.1);
+            i += best.0;
+        } else {
+            write_literal(&mut w, data[i] as u32);
+            i += 1;
+        }
+    }
+    write_literal(&mut w, 256); // End of block
+
+    let mut out = w.finish();
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+// Compress RGB pixels into the zlib stream of IDAT (or fdAT) data.
+pub fn compress_rgb(width: u32, height: u32, rgb: &[u8]) -> Vec<u8> {
+    let stride = width as usize * 3;
+    let mut raw = Vec::with_capacity((stride + 1) * height as usize);
+
+    for row in rgb.chunks(stride) {
+        raw.push(0); // Filter type None
+        raw.extend_from_slice(row);
+    }
+
+    zlib(&raw, &[1, 3, stride + 1])
+}
+
+pub fn write_header<W: Write>(out: &mut W, width: u32, height: u32) -> io::Result<()> {
+    let mut ihdr = Vec::new();
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]); // 8-bit RGB, no interlacing
+
+    out.write_all(&PNG_SIGNATURE)?;
+    write_chunk(out, b"IHDR", &ihdr)
+}
+
+// Write a complete PNG of width x height RGB pixels.
+pub fn write_png<W: Write>(out: &mut W, width: u32, height: u32, rgb: &[u8]) -> io::Result<()> {
+    write_header(out, width, height)?;
+    write_chunk(out, b"IDAT", &compress_rgb(width, height, rgb))?;
+    write_chunk(out, b"IEND", &[])
+}
+
+// Write an animated PNG. Each frame is RGB pixels plus how many 60ths of a
+// second it stays on screen. The animation loops forever.
+pub fn write_apng<W: Write>(out: &mut W, width: u32, height: u32,
+                            frames: &[(Vec<u8>, u32)]) -> io::Result<()> {
+    write_header(out, width, height)?;
+
+    let mut actl = Vec::new();
+    actl.extend_from_slice(&(frames.len() as u32).to_be_bytes());
+    actl.extend_from_slice(&0u32.to_be_bytes()); // Loop forever
+    write_chunk(out, b"acTL", &actl)?;
+
+    // fcTL and fdAT chunks share one sequence counter
+    let mut seq = 0u32;
+    for (i, &(ref rgb, delay)) in frames.iter().enumerate() {
+        let mut fctl = Vec::new();
+        fctl.extend_from_slice(&seq.to_be_bytes());
+        fctl.extend_from_slice(&width.to_be_bytes());
+        fctl.extend_from_slice(&height.to_be_bytes());
+        fctl.extend_from_slice(&0u32.to_be_bytes()); // x offset
+        fctl.extend_from_slice(&0u32.to_be_bytes()); // y offset
+        fctl.extend_from_slice(&(delay.min(0xFFFF) as u16).to_be_bytes());
+        fctl.extend_from_slice(&60u16.to_be_bytes());
+        fctl.extend_from_slice(&[0, 0]); // No disposal, no blending
+        write_chunk(out, b"fcTL", &fctl)?;
+        seq += 1;
+
+        let data = compress_rgb(width, height, rgb);
+        if i == 0 {
+            write_chunk(out, b"IDAT", &data)?;
+        } else {
+            let mut fdat = Vec::with_capacity(data.len() + 4);
+            fdat.extend_from_slice(&seq.to_be_bytes());
+            fdat.extend_from_slice(&data);
+            write_chunk(out, b"fdAT", &fdat)?;
+            seq += 1;
+        }
+    }
+
+    write_chunk(out, b"IEND", &[])
+}
@@ -0,0 +1,179 @@
+//! Where a ROM spends its instructions.
+
+use std::collections::HashMap;
+use std::io;
+use std::io::prelude::*;
+use std::time::{Duration, Instant};
+
+use cpu::ExecResult;
+use labels::Labels;
+use parsing::Instruction;
+
+// How many entries the hotspot tables list.
+const REPORT_ENTRIES: usize = 15;
+
+#[derive(Clone, Copy, Debug, Default)]
+struct RoutineCost {
+    calls: u64,
+    self_count: u64,  // Instructions executed in the routine itself
+    total_count: u64, // Including the routines it called
+}
+
+/// A Profiler counts where instructions are spent: per address, per
+/// Instruction variant and per routine (a Call target), along with how
+/// many instructions run each frame and how long LD Vx, K sat waiting.
+pub struct Profiler {
+    addr_counts: Vec<u64>,
+    variant_counts: HashMap<&'static str, u64>,
+    routines: HashMap<u16, RoutineCost>,
+    call_stack: Vec<u16>, // Entry addresses of the active routines
+
+    start: Instant,
+    frame_count: u64,     // Instructions in the current frame
+    frames: Vec<u64>,     // Instructions in each completed frame
+
+    ldk_count: u64,       // LD Vx, K runs again each instruction it waits
+    total: u64,
+}
+
+fn percent(n: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        100.0 * n as f64 / total as f64
+    }
+}
+
+fn seconds(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 / 1e9
+}
+
+impl Profiler {
+    /// start is where execution starts, the entry of the "main" routine.
+    pub fn new(start: u16) -> Profiler {
+        Profiler {
+            addr_counts: vec![0; 4096],
+            variant_counts: HashMap::new(),
+            routines: HashMap::new(),
+            call_stack: vec![start],
+
+            start: Instant::now(),
+            frame_count: 0,
+            frames: Vec::new(),
+
+            ldk_count: 0,
+            total: 0,
+        }
+    }
+
+    /// Record one executed instruction. pc is the address it was fetched
+    /// from.
+    pub fn record(&mut self, pc: u16, ins: &Instruction, res: ExecResult) {
+        self.total += 1;
+        self.addr_counts[pc as usize] += 1;
+        *self.variant_counts.entry(ins.name()).or_insert(0) += 1;
+
+        // Charge the instruction to every active routine, once each even
+        // if a routine is on the stack more than once through recursion.
+        for i in 0..self.call_stack.len() {
+            let entry = self.call_stack[i];
+            if self.call_stack[..i].contains(&entry) {
+                continue;
+            }
+
+            let cost = self.routines.entry(entry).or_default();
+            cost.total_count += 1;
+            if i == self.call_stack.len() - 1 {
+                cost.self_count += 1;
+            }
+        }
+
+        if res == ExecResult::Success {
+            match *ins {
+                Instruction::Call(addr) => {
+                    self.call_stack.push(addr);
+                    self.routines.entry(addr).or_default().calls += 1;
+                },
+                Instruction::Ret if self.call_stack.len() > 1 => {
+                    self.call_stack.pop();
+                },
+                Instruction::LdK(_) => self.ldk_count += 1,
+                _ => (),
+            }
+        }
+
+        self.frame_count += 1;
+    }
+
+    /// Record the end of a frame of emulated time, when the timers tick.
+    pub fn frame_end(&mut self) {
+        self.frames.push(self.frame_count);
+        self.frame_count = 0;
+    }
+
+    /// Write a hotspot report. memory is used to disassemble the hottest
+    /// addresses and labels to name routines.
+    pub fn write_report<W: Write>(&self, out: &mut W, memory: &[u8],
+                                  labels: &Labels) -> io::Result<()> {
+        let total = self.total;
+
+        writeln!(out, "Executed {} instructions in {:.2}s",
+                 total, seconds(self.start.elapsed()))?;
+
+        if !self.frames.is_empty() {
+            let sum: u64 = self.frames.iter().sum();
+            writeln!(out, "Instructions per frame: avg {:.1}, min {}, max {} over {} frames",
+                     sum as f64 / self.frames.len() as f64,
+                     self.frames.iter().min().unwrap(),
+                     self.frames.iter().max().unwrap(),
+                     self.frames.len())?;
+        }
+        writeln!(out, "Waiting in LD Vx, K: {} instructions ({:.2}%)",
+                 self.ldk_count, percent(self.ldk_count, total))?;
+
+        let mut routines: Vec<(&u16, &RoutineCost)> = self.routines.iter().collect();
+        routines.sort_by(|a, b| b.1.total_count.cmp(&a.1.total_count).then(a.0.cmp(b.0)));
+
+        writeln!(out, "\nTop routines:")?;
+        writeln!(out, "  {:<20} {:>8} {:>12} {:>7} {:>12} {:>7}",
+                 "routine", "calls", "self", "self%", "total", "total%")?;
+        for &(&entry, cost) in routines.iter().take(REPORT_ENTRIES) {
+            let name = if entry == self.call_stack[0] && labels.get(entry).is_none() {
+                String::from("main")
+            } else {
+                labels.name(entry)
+            };
+            writeln!(out, "  {:<20} {:>8} {:>12} {:>6.2}% {:>12} {:>6.2}%",
+                     name, cost.calls,
+                     cost.self_count, percent(cost.self_count, total),
+                     cost.total_count, percent(cost.total_count, total))?;
+        }
+
+        let mut addrs: Vec<usize> = (0..self.addr_counts.len())
+            .filter(|&a| self.addr_counts[a] > 0)
+            .collect();
+        addrs.sort_by(|&a, &b| self.addr_counts[b].cmp(&self.addr_counts[a]).then(a.cmp(&b)));
+
+        writeln!(out, "\nHottest addresses:")?;
+        for &addr in addrs.iter().take(REPORT_ENTRIES) {
+            let count = self.addr_counts[addr];
+            let ins = match Instruction::from_slice_one(&memory[addr..(addr + 2)]) {
+                Some(ins) => format!("{}", ins),
+                None => String::from("??"),
+            };
+            let label = labels.get(addr as u16).map(|l| format!("{}:", l)).unwrap_or_default();
+            writeln!(out, "  {:03X} {:<16} {:>12} {:>6.2}%  {}",
+                     addr, label, count, percent(count, total), ins)?;
+        }
+
+        let mut variants: Vec<(&&'static str, &u64)> = self.variant_counts.iter().collect();
+        variants.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+
+        writeln!(out, "\nInstruction variants:")?;
+        for &(name, &count) in &variants {
+            writeln!(out, "  {:<8} {:>12} {:>6.2}%", name, count, percent(count, total))?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+//! The behaviours CHIP8 interpreters disagree on, as named profiles.
+
+/// Quirks are the points where CHIP8 interpreters disagree on what an
+/// instruction does. ROMs written for one interpreter can misbehave on
+/// another, so the behaviour is chosen per run from a named profile.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    /// Name of the profile, e.g. "vip"
+    pub name: &'static str,
+    /// FX0A completes when the key is released, not pressed
+    pub key_release: bool,
+    /// 8XY6 and 8XYE shift VY into VX, rather than VX itself
+    pub shift_vy: bool,
+    /// FX55 and FX65 leave I just past the last register
+    pub load_store_i: bool,
+    /// BXNN jumps to XNN plus VX, rather than NNN plus V0
+    pub jump_vx: bool,
+    /// Sprites are cut off at the screen edges, not wrapped
+    pub clip: bool,
+    /// 8XY1, 8XY2 and 8XY3 clear VF
+    pub vf_reset: bool,
+}
+
+// Built-in profiles, by name. The first is the default.
+static PROFILES: [Quirks; 3] = [
+    // What this emulator has always done, which suits most ROMs: shifts
+    // and FX55/FX65 as on the CHIP-48, and sprites wrapping around the
+    // screen edges. Only FX0A follows the VIP.
+    Quirks {
+        name: "classic",
+        key_release: true,
+        shift_vy: false,
+        load_store_i: false,
+        jump_vx: false,
+        clip: false,
+        vf_reset: false,
+    },
+    // The original interpreter on the COSMAC VIP
+    Quirks {
+        name: "vip",
+        key_release: true,
+        shift_vy: true,
+        load_store_i: true,
+        jump_vx: false,
+        clip: true,
+        vf_reset: true,
+    },
+    // CHIP-48 and SUPER-CHIP 1.1 on HP calculators
+    Quirks {
+        name: "schip",
+        key_release: false,
+        shift_vy: false,
+        load_store_i: false,
+        jump_vx: true,
+        clip: true,
+        vf_reset: false,
+    },
+];
+
+// Platforms, by name, with the profile for each.
+static PLATFORMS: [(&str, &str); 2] = [
+    ("chip8", "vip"),
+    ("schip", "schip"),
+];
+
+impl Quirks {
+    /// A profile by name.
+    pub fn profile(name: &str) -> Option<Quirks> {
+        PROFILES.iter().find(|q| q.name == name).cloned()
+    }
+
+    /// The names of the profiles.
+    pub fn profile_names() -> Vec<&'static str> {
+        PROFILES.iter().map(|q| q.name).collect()
+    }
+
+    /// The quirks of the platform (the machine and interpreter) a ROM was
+    /// written for.
+    pub fn platform(name: &str) -> Option<Quirks> {
+        PLATFORMS.iter().find(|p| p.0 == name).and_then(|p| Quirks::profile(p.1))
+    }
+
+    /// The platforms quirks can be chosen by.
+    pub fn platform_names() -> Vec<&'static str> {
+        PLATFORMS.iter().map(|p| p.0).collect()
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        PROFILES[0]
+    }
+}
@@ -0,0 +1,94 @@
+//! The source of numbers for RND (CXNN). Each starts from a seed, so that a
+//! run can be repeated exactly, and its state is part of the machine's: it
+//! is saved and restored along with the registers.
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+/// The generators RND can use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RandomKind {
+    /// A good general purpose generator
+    Xorshift,
+    /// After the COSMAC VIP interpreter's routine
+    Vip,
+}
+
+static KINDS: [(&str, RandomKind); 2] = [
+    ("xorshift", RandomKind::Xorshift),
+    ("vip",      RandomKind::Vip),
+];
+
+impl RandomKind {
+    /// A generator by name.
+    pub fn parse(s: &str) -> Option<RandomKind> {
+        KINDS.iter().find(|k| k.0 == s).map(|k| k.1)
+    }
+
+    /// The generator's name, as parse takes it.
+    pub fn name(self) -> &'static str {
+        KINDS.iter().find(|k| k.1 == self).unwrap().0
+    }
+}
+
+/// A generator and its state.
+#[derive(Clone, Debug)]
+pub enum Random {
+    /// The xorshift generator
+    Xorshift(XorShiftRng),
+
+    /// The VIP steps a pointer through a page of its interpreter's code and
+    /// adds the byte it finds there into a running value. Its interpreter
+    /// isn't in memory here, so this walks the first page of the program
+    /// instead, which makes the sequence depend on the ROM much as the
+    /// VIP's depended on the interpreter.
+    Vip {
+        /// Offset of the next byte in the page
+        pointer: u8,
+        /// The running value
+        value: u8,
+    },
+}
+
+// Where the VIP routine reads its bytes from.
+const VIP_PAGE: usize = 0x200;
+
+impl Random {
+    /// A generator of the given kind, started from seed.
+    pub fn new(kind: RandomKind, seed: u64) -> Random {
+        match kind {
+            // XorShiftRng needs a seed that isn't all zeros, so the halves
+            // of seed are mixed with constants
+            RandomKind::Xorshift => {
+                let lo = seed as u32;
+                let hi = (seed >> 32) as u32;
+                Random::Xorshift(XorShiftRng::from_seed([lo, hi, lo ^ 0x9E3779B9,
+                                                         hi ^ 0x7F4A7C15]))
+            },
+            RandomKind::Vip => Random::Vip {
+                pointer: seed as u8,
+                value: (seed >> 8) as u8,
+            },
+        }
+    }
+
+    /// Which generator this is.
+    pub fn kind(&self) -> RandomKind {
+        match *self {
+            Random::Xorshift(_) => RandomKind::Xorshift,
+            Random::Vip { .. } => RandomKind::Vip,
+        }
+    }
+
+    /// The next random byte. memory is the machine's, for the VIP routine.
+    pub fn next(&mut self, memory: &[u8]) -> u8 {
+        match *self {
+            Random::Xorshift(ref mut rng) => rng.gen(),
+            Random::Vip { ref mut pointer, ref mut value } => {
+                *pointer = pointer.wrapping_add(1);
+                *value = value.wrapping_add(memory[VIP_PAGE + *pointer as usize])
+                    .rotate_right(1);
+                *value
+            },
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! Records the screen once per 60 Hz frame of emulated time into an
+//! animated GIF or APNG, picked by the file extension. Optionally also
+//! writes every frame as a numbered PNG plus a WAV of the beeper, for
+//! editing elsewhere. Frames are kept in memory and encoded when the
+//! recording finishes.
+
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use palette::Palette;
+use capture;
+use png;
+use gif;
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+
+const SAMPLE_RATE: u32 = 44100;
+const SAMPLES_PER_FRAME: u32 = SAMPLE_RATE / 60;
+const BEEP_HZ: u32 = 440;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    Gif,
+    Apng,
+}
+
+/// A recording of the screen, and optionally the sound.
+pub struct Recorder {
+    path: String,
+    format: Format,
+    scale: usize,
+    palette: Palette,
+    raw: bool, // Also write the frame sequence and WAV
+
+    frames: Vec<(Vec<bool>, u32)>, // Distinct screens and their length in frames
+    beeps: Vec<bool>,              // Whether the beeper sounded, per frame
+}
+
+impl Recorder {
+    /// Start a recording to path, which must end in .gif, .png or .apng.
+    /// Each screen pixel becomes scale x scale pixels in the palette's colors.
+    pub fn new(path: &str, scale: u32, palette: Palette, raw: bool) -> Result<Recorder, String> {
+        let ext = Path::new(path).extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let format = match ext.as_str() {
+            "gif"          => Format::Gif,
+            "png" | "apng" => Format::Apng,
+            _ => return Err(format!("Can't record to {}: use a .gif, .png or .apng file", path)),
+        };
+
+        // GIF sizes are 16 bit
+        let scale = (scale.max(1) as usize).min(0xFFFF / WIDTH);
+
+        Ok(Recorder {
+            path: path.to_string(),
+            format: format,
+            scale: scale,
+            palette: palette,
+            raw: raw,
+
+            frames: Vec::new(),
+            beeps: Vec::new(),
+        })
+    }
+
+    /// Where the recording is saved.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Add one 60 Hz frame. Unchanged screens extend the previous frame.
+    pub fn frame(&mut self, screen: &[bool], beeping: bool) {
+        self.beeps.push(beeping);
+
+        if let Some(last) = self.frames.last_mut() {
+            if last.0 == screen {
+                last.1 += 1;
+                return;
+            }
+        }
+        self.frames.push((screen.to_vec(), 1));
+    }
+
+    /// Encode everything recorded so far and write it out.
+    pub fn finish(self) -> io::Result<()> {
+        let w = WIDTH * self.scale;
+        let h = HEIGHT * self.scale;
+        let mut out = BufWriter::new(File::create(&self.path)?);
+
+        match self.format {
+            Format::Gif => {
+                let frames: Vec<(Vec<u8>, u32)> = self.frames.iter()
+                    .map(|&(ref screen, len)| (self.indices(screen), len))
+                    .collect();
+                gif::write_gif(&mut out, w as u16, h as u16, &self.palette.colors, &frames)?;
+            },
+            Format::Apng => {
+                let frames: Vec<(Vec<u8>, u32)> = self.frames.iter()
+                    .map(|&(ref screen, len)| (self.rgb(screen), len))
+                    .collect();
+                png::write_apng(&mut out, w as u32, h as u32, &frames)?;
+            },
+        }
+        out.flush()?;
+
+        if self.raw {
+            self.write_frames()?;
+            self.write_wav()?;
+        }
+
+        Ok(())
+    }
+
+    fn rgb(&self, screen: &[bool]) -> Vec<u8> {
+        capture::screen_rgb(screen, WIDTH, HEIGHT, self.scale, &self.palette)
+    }
+
+    // Scale a screen up into palette indices.
+    fn indices(&self, screen: &[bool]) -> Vec<u8> {
+        let mut indices = Vec::with_capacity(WIDTH * HEIGHT * self.scale * self.scale);
+
+        for y in 0..(HEIGHT * self.scale) {
+            for x in 0..(WIDTH * self.scale) {
+                indices.push(screen[(y / self.scale) * WIDTH + x / self.scale] as u8);
+            }
+        }
+
+        indices
+    }
+
+    // The path with its extension replaced, e.g. clip.gif to clip.wav.
+    fn sibling(&self, suffix: &str) -> PathBuf {
+        let path = Path::new(&self.path);
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        path.with_file_name(format!("{}{}", stem, suffix))
+    }
+
+    // Every frame as a PNG in clip-frames/, numbered from 000000.png.
+    fn write_frames(&self) -> io::Result<()> {
+        let dir = self.sibling("-frames");
+        fs::create_dir_all(&dir)?;
+
+        let mut n = 0;
+        for &(ref screen, len) in &self.frames {
+            let rgb = self.rgb(screen);
+            for _ in 0..len {
+                let fname = dir.join(format!("{:06}.png", n));
+                let mut out = BufWriter::new(File::create(fname)?);
+                png::write_png(&mut out, (WIDTH * self.scale) as u32,
+                               (HEIGHT * self.scale) as u32, &rgb)?;
+                n += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    // The beeper as 8-bit mono PCM, a square wave while it sounded.
+    fn write_wav(&self) -> io::Result<()> {
+        let len = self.beeps.len() as u32 * SAMPLES_PER_FRAME;
+        let mut out = BufWriter::new(File::create(self.sibling(".wav"))?);
+
+        out.write_all(b"RIFF")?;
+        out.write_all(&(36 + len).to_le_bytes())?;
+        out.write_all(b"WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?; // PCM
+        out.write_all(&1u16.to_le_bytes())?; // Mono
+        out.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        out.write_all(&SAMPLE_RATE.to_le_bytes())?; // Bytes per second
+        out.write_all(&1u16.to_le_bytes())?; // Bytes per sample
+        out.write_all(&8u16.to_le_bytes())?; // Bits per sample
+        out.write_all(b"data")?;
+        out.write_all(&len.to_le_bytes())?;
+
+        // Keep the wave's phase running across frames so it doesn't click
+        let mut t = 0u32;
+        for &beeping in &self.beeps {
+            for _ in 0..SAMPLES_PER_FRAME {
+                let sample = if !beeping {
+                    0x80
+                } else if (t * 2 * BEEP_HZ / SAMPLE_RATE) & 1 == 0 {
+                    0xA0
+                } else {
+                    0x60
+                };
+                out.write_all(&[sample])?;
+                t = (t + 1) % SAMPLE_RATE;
+            }
+        }
+
+        out.flush()
+    }
+}
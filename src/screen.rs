@@ -0,0 +1,117 @@
+//! The CHIP8 screen buffer, shared by all frontends.
+
+/// Width of the screen in pixels.
+pub const SCREEN_WIDTH: u8 = 64;
+/// Height of the screen in pixels.
+pub const SCREEN_HEIGHT: u8 = 32;
+
+/// What drawing a sprite did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DrawResult {
+    /// The sprite erased a lit pixel
+    Collision,
+    /// No pixel was erased
+    Success,
+}
+
+/// The pixels of the CHIP8 screen, and whether they changed since they
+/// were last presented.
+pub struct Screen {
+    pixels: [bool; 64 * 32],
+    dirty: bool, // Changed since it was last presented
+}
+
+impl Screen {
+    /// A blank screen.
+    pub fn new() -> Screen {
+        Screen {
+            pixels: [false; 64 * 32],
+            dirty: false,
+        }
+    }
+
+    /// Draw a CHIP8 sprite from a slice to (x, y), wrapping around the edges,
+    /// or with clip set cutting it off at them (the position itself always
+    /// wraps). If a collision occurs, return Collision. Otherwise, return
+    /// Success.
+    pub fn draw_sprite(&mut self, x: u8, y: u8, slice: &[u8], clip: bool) -> DrawResult {
+        let w = SCREEN_WIDTH as usize;
+        let h = SCREEN_HEIGHT as usize;
+        let x = x as usize % w;
+        let y = y as usize % h;
+        let mut collision = false;
+
+        for (i, row) in slice.iter().enumerate() {
+            if clip && y + i >= h {
+                break;
+            }
+            for j in 0..8 {
+                if clip && x + j >= w {
+                    break;
+                }
+                let scy = (y + i) % h;
+                let scx = (x + j) % w;
+
+                let scindex = scy * w + scx;
+                let set = (row >> (7 - j)) & 1 == 1;
+
+                if self.pixels[scindex] && set {
+                    collision = true;
+                }
+
+                self.pixels[scindex] ^= set;
+            }
+        }
+
+        self.dirty = true;
+
+        if collision {
+            DrawResult::Collision
+        } else {
+            DrawResult::Success
+        }
+    }
+
+    /// Clear the screen.
+    pub fn clear(&mut self) {
+        for i in 0..self.pixels.len() {
+            self.pixels[i] = false;
+        }
+        self.dirty = true;
+    }
+
+    /// One bool per pixel in row-major order.
+    pub fn pixels(&self) -> &[bool] {
+        &self.pixels
+    }
+
+    /// Set the given (index, value) pixels.
+    /// Used to rewind the screen when stepping backwards.
+    pub fn restore_pixels(&mut self, pixels: &[(usize, bool)]) {
+        for &(i, set) in pixels {
+            self.pixels[i] = set;
+        }
+        self.dirty = true;
+    }
+
+    /// Whether the screen changed since it was last presented.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Ask for the screen to be presented again, e.g. after a palette change.
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Note that the screen as it is now has been presented.
+    pub fn mark_presented(&mut self) {
+        self.dirty = false;
+    }
+}
+
+impl Default for Screen {
+    fn default() -> Screen {
+        Screen::new()
+    }
+}
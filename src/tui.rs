@@ -0,0 +1,289 @@
+//! A frontend that draws the screen in the terminal.
+
+use std::io;
+use std::io::prelude::*;
+use std::mem;
+use std::time::{Duration, Instant};
+
+use libc;
+
+use palette::Palette;
+use keymap::{KeyMap, Binding, Hotkey};
+use screen::{Screen, SCREEN_WIDTH, SCREEN_HEIGHT};
+use frontend::Frontend;
+
+// A frontend for terminals, e.g. over SSH.
+// The screen is drawn with Unicode half blocks, two pixels per character
+// cell, in the palette's colors (this needs a 24-bit color terminal).
+// Terminals only report key presses, not releases, so a key counts as held
+// for a moment after each press, and auto repeat keeps it held. Of the
+// hotkeys, quit and the run controls work here.
+
+// The screen is presented at most once per 60 Hz frame, give or take
+// jitter in the CPU's pacing.
+const PRESENT_GAP_NANOS: u32 = 1000000000 / 60 / 4 * 3;
+
+// How long a key stays down after a press, and after each auto repeat.
+// The first repeat comes later than the ones after it.
+const KEY_TAP_MILLIS: u64 = 250;
+const KEY_REPEAT_MILLIS: u64 = 100;
+
+// The escape sequences terminals send for function keys, without the
+// leading Escape.
+static ESCAPE_KEYS: [(&[u8], &str); 12] = [
+    (b"OP", "F1"),    (b"OQ", "F2"),    (b"OR", "F3"),    (b"OS", "F4"),
+    (b"[15~", "F5"),  (b"[17~", "F6"),  (b"[18~", "F7"),  (b"[19~", "F8"),
+    (b"[20~", "F9"),  (b"[21~", "F10"), (b"[23~", "F11"), (b"[24~", "F12"),
+];
+
+// Beeps closer together than this are one sound, and ring the bell once.
+const BEEP_GAP_MILLIS: u64 = 100;
+
+/// The terminal frontend. Each character cell shows two pixels, one
+/// above the other.
+pub struct Tui {
+    screen: Screen,
+    palette: Palette,
+    keymap: KeyMap,
+    last_present: Instant,
+
+    keys: [bool; 16],
+    held_until: [Instant; 16], // When each pressed key is released
+    last_beep: Option<Instant>,
+    quit: bool,
+    commands: Vec<Hotkey>, // Run control hotkeys for the CPU
+
+    active: bool,                   // We hold the terminal
+    saved: Option<libc::termios>,   // Terminal settings to restore
+}
+
+impl Tui {
+    /// A frontend drawing in the terminal, which it takes over.
+    pub fn new(palette: Palette, keymap: KeyMap) -> Tui {
+        Tui {
+            screen: Screen::new(),
+            palette: palette,
+            keymap: keymap,
+            last_present: Instant::now(),
+
+            keys: [false; 16],
+            held_until: [Instant::now(); 16],
+            last_beep: None,
+            quit: false,
+            commands: Vec::new(),
+
+            active: false,
+            saved: None,
+        }
+    }
+
+    // Take over the terminal: raw, non-blocking input on stdin and an
+    // alternate screen with the cursor hidden.
+    fn enter(&mut self) {
+        if self.active {
+            return;
+        }
+
+        // Input only works if stdin is a terminal
+        unsafe {
+            let mut t: libc::termios = mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut t) == 0 {
+                self.saved = Some(t);
+                libc::cfmakeraw(&mut t);
+                t.c_oflag |= libc::OPOST;
+                t.c_cc[libc::VMIN] = 0;
+                t.c_cc[libc::VTIME] = 0;
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &t);
+            }
+        }
+
+        print!("\x1b[?1049h\x1b[?25l\x1b[2J");
+        io::stdout().flush().unwrap();
+
+        self.active = true;
+        self.screen.mark_dirty();
+    }
+
+    // Act on a key typed, named as in key maps.
+    fn key(&mut self, name: &str) {
+        match self.keymap.binding(name) {
+            Some(Binding::Pad(k)) => self.press(k as usize),
+            Some(Binding::Hotkey(Hotkey::Quit)) => self.quit = true,
+            Some(Binding::Hotkey(h)) if h.is_run_control() => self.commands.push(h),
+            _ => (),
+        }
+    }
+
+    fn press(&mut self, k: usize) {
+        let millis = if self.keys[k] { KEY_REPEAT_MILLIS } else { KEY_TAP_MILLIS };
+
+        self.keys[k] = true;
+        self.held_until[k] = Instant::now() + Duration::from_millis(millis);
+    }
+
+    // Handle a chunk of bytes read from the terminal.
+    fn handle_input(&mut self, bytes: &[u8]) {
+        let mut i = 0;
+
+        while i < bytes.len() {
+            match bytes[i] {
+                // Ctrl-C, since raw mode turns off the interrupt signal
+                0x03 => self.quit = true,
+
+                // A lone byte is the Escape key. Otherwise it starts an
+                // escape sequence, as sent for arrow and function keys.
+                0x1B => {
+                    if i + 1 == bytes.len() {
+                        self.key("Escape");
+                    } else if bytes[i + 1] == b'[' || bytes[i + 1] == b'O' {
+                        let start = i;
+                        i += 2;
+                        while i < bytes.len() && !(0x40..=0x7E).contains(&bytes[i]) {
+                            i += 1;
+                        }
+
+                        let end = (i + 1).min(bytes.len());
+                        if let Some(&(_, name)) = ESCAPE_KEYS.iter()
+                            .find(|k| k.0 == &bytes[(start + 1)..end]) {
+                            self.key(name);
+                        }
+                    } else {
+                        i += 1;
+                    }
+                },
+
+                b'\t' => self.key("Tab"),
+                b' ' => self.key("Space"),
+
+                c => self.key(&(c as char).to_ascii_uppercase().to_string()),
+            }
+
+            i += 1;
+        }
+    }
+
+    // Present the screen if it changed and a frame has passed since the
+    // last time.
+    fn present_if_due(&mut self) {
+        if self.screen.is_dirty() &&
+            self.last_present.elapsed() >= Duration::new(0, PRESENT_GAP_NANOS) {
+            self.present();
+        }
+    }
+}
+
+impl Frontend for Tui {
+    fn screen(&self) -> &Screen {
+        &self.screen
+    }
+
+    fn screen_mut(&mut self) -> &mut Screen {
+        &mut self.screen
+    }
+
+    fn keys(&self) -> &[bool; 16] {
+        &self.keys
+    }
+
+    // Read all pending input, release keys whose time is up, then present
+    // the screen if a frame is due.
+    fn draw_events(&mut self) {
+        self.enter();
+
+        if self.saved.is_some() {
+            let mut buf = [0u8; 64];
+            loop {
+                match io::stdin().read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => self.handle_input(&buf[..n]),
+                }
+            }
+        }
+
+        let now = Instant::now();
+        for k in 0..16 {
+            if self.keys[k] && now >= self.held_until[k] {
+                self.keys[k] = false;
+            }
+        }
+
+        self.present_if_due();
+    }
+
+    fn present(&mut self) {
+        self.enter();
+
+        let [fr, fg, fb] = self.palette.foreground();
+        let [br, bg, bb] = self.palette.background();
+        let width = SCREEN_WIDTH as usize;
+        let pixels = self.screen.pixels();
+
+        let mut out = format!("\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m", fr, fg, fb, br, bg, bb);
+        for row in 0..(SCREEN_HEIGHT as usize / 2) {
+            out.push_str(&format!("\x1b[{};1H", row + 1));
+            for x in 0..width {
+                let top = pixels[2 * row * width + x];
+                let bottom = pixels[(2 * row + 1) * width + x];
+                out.push(match (top, bottom) {
+                    (false, false) => ' ',
+                    (true, false)  => '\u{2580}', // Upper half block
+                    (false, true)  => '\u{2584}', // Lower half block
+                    (true, true)   => '\u{2588}', // Full block
+                });
+            }
+        }
+        out.push_str("\x1b[0m");
+
+        let stdout = io::stdout();
+        let mut lock = stdout.lock();
+        lock.write_all(out.as_bytes()).unwrap();
+        lock.flush().unwrap();
+
+        self.screen.mark_presented();
+        self.last_present = Instant::now();
+    }
+
+    fn take_commands(&mut self) -> Vec<Hotkey> {
+        mem::take(&mut self.commands)
+    }
+
+    // Whether the quit key or Ctrl-C was pressed.
+    fn quit_requested(&self) -> bool {
+        self.quit
+    }
+
+    // Ring the terminal bell when a sound starts.
+    fn beep(&mut self) {
+        let now = Instant::now();
+        let gap = Duration::from_millis(BEEP_GAP_MILLIS);
+
+        if self.last_beep.is_none_or(|t| now - t > gap) {
+            print!("\x07");
+            io::stdout().flush().unwrap();
+        }
+        self.last_beep = Some(now);
+    }
+
+    // Restore the terminal settings and leave the alternate screen.
+    fn suspend(&mut self) {
+        if !self.active {
+            return;
+        }
+
+        print!("\x1b[0m\x1b[?25h\x1b[?1049l");
+        io::stdout().flush().unwrap();
+
+        if let Some(t) = self.saved.take() {
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &t);
+            }
+        }
+        self.active = false;
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        self.suspend();
+    }
+}